@@ -5,7 +5,7 @@ use std::{
 
 use log::{error, info};
 
-use crate::slide::video;
+use crate::slide::{chapter, job, loudness, manifest, profile, transition, video, voicevox};
 
 mod slide;
 
@@ -21,15 +21,45 @@ async fn main() {
 
     // リソースとなるファイルから動画のセクションを生成
     let text_filename = PathBuf::from(std::env::var("DEFAULT_RESOURCE_FILE_PATH").unwrap());
-    let mut sections = slide::section::Section::create_vec(text_filename).unwrap();
+    let mut sections = slide::section::Section::create_vec(text_filename.clone()).unwrap();
     info!("resource data for section: {:?}", sections);
 
+    // 前回失敗したジョブがあれば、完了した音声と動画を再利用する
+    let mut job = match job::Job::load(&text_filename) {
+        Ok(job) => job,
+        Err(e) => {
+            error!("job Error: {}", e);
+            return;
+        }
+    };
+
+    // 音声を生成する場合は、始める前にエンジンが応答するか確認する
+    let needs_voice = sections
+        .iter()
+        .flat_map(|section| section.contents.iter())
+        .any(|content| job.voice(&content.key).is_none());
+    if needs_voice {
+        let checked = match voicevox::Client::from_env() {
+            Ok(client) => client.health_check().await,
+            Err(e) => Err(e),
+        };
+        match checked {
+            Ok(version) => {
+                info!("voicevox engine: {}", version);
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+
     // セクションのテキストを音声に変換
     // sections.voicesにはコンテンツkey対応の音声ファイルが格納される
     for section in sections.iter_mut() {
         // 画像及び動画一つに対して、複数の音声が出力される
         // 段落ごとに音声を生成
-        match section.create_voices().await {
+        match section.create_voices(&mut job).await {
             Ok(_) => {
                 info!("to_voices: {:?}", section);
             }
@@ -38,17 +68,52 @@ async fn main() {
                 return;
             }
         };
+    }
 
+    // 出力プロファイルごとに動画を生成
+    // 音声はプロファイル間で共有する
+    let profiles = match profile::Profile::from_env() {
+        Ok(profiles) if profiles.is_empty() => vec![None],
+        Ok(profiles) => profiles.into_iter().map(Some).collect(),
+        Err(e) => {
+            error!("profile Error: {}", e);
+            return;
+        }
+    };
+    for profile in profiles.iter() {
+        match create_video(&mut sections, profile.as_ref(), &mut job).await {
+            Ok(output_file) => {
+                info!("success: {:?}", output_file);
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+
+    // すべて完了したらジョブの状態を削除する
+    if let Err(e) = job.finish() {
+        error!("job Error: {}", e);
+    }
+}
+
+// セクションごとの動画を生成し、連結した動画ファイルを返す
+async fn create_video(
+    sections: &mut [slide::section::Section],
+    profile: Option<&profile::Profile>,
+    job: &mut job::Job,
+) -> Result<String, String> {
+    for section in sections.iter_mut() {
         // 段落ごとに動画を生成
         // - テキスト・音声ファイル群を画像に焼き付け
         // - セッションVideoに動画ファイルパスを格納
-        match section.create_video().await {
+        match section.create_video(profile, job).await {
             Ok(_) => {
                 info!("inner create_video: {:?}", section);
             }
             Err(e) => {
-                error!("failed create_video error: {}", e);
-                return;
+                return Err(format!("failed create_video error: {}", e));
             }
         };
     }
@@ -61,20 +126,95 @@ async fn main() {
 
     // 動画連結のためのファイルを作成
     // 出力先ファイルを作成
-    let (concat_file, output_file) = video::create_output_files(concated_videos);
+    // プロファイルを指定した場合はファイル名にプロファイル名を付ける
+    let (concat_file, output_file) = video::create_output_files(concated_videos.clone());
+    let output_file = match profile {
+        Some(profile) => profile.output_filename(&output_file),
+        None => output_file,
+    };
+
+    // セクション境界のトランジション
+    // セクションの指定がなければ全体設定を使う
+    let default_transition = transition::Transition::from_env();
+    let transitions = sections
+        .iter()
+        .filter(|section| section.video.is_some())
+        .skip(1)
+        .map(|section| section.transition.or(default_transition))
+        .collect::<Vec<_>>();
+
+    let durations = sections
+        .iter()
+        .filter(|section| section.video.is_some())
+        .map(|section| section.duration())
+        .collect::<Vec<f64>>();
 
     // 動画を連結
-    match video::concat(concat_file, output_file.clone()).await {
+    // トランジションがなければ再エンコードせずに連結する
+    let concated = if transitions.iter().all(|t| t.is_none()) {
+        video::concat(concat_file, output_file.clone()).await
+    } else {
+        video::concat_with_transitions(
+            concated_videos,
+            durations.clone(),
+            transitions.clone(),
+            output_file.clone(),
+        )
+        .await
+    };
+
+    match concated {
         Ok(output_video_filepath) => {
             info!("last video concated: {:?}", output_video_filepath);
         }
         Err(e) => {
-            error!("last concat Error: {}", e);
-            return;
+            return Err(format!("last concat Error: {}", e));
+        }
+    };
+
+    // 最終出力に対するラウドネス正規化
+    if loudness::Mode::from_env() == loudness::Mode::Mix {
+        let target = loudness::Target::from_env();
+        if let Err(e) = loudness::normalize_video(&output_file, &target).await {
+            return Err(format!("loudnorm Error: {}", e));
+        }
+    }
+
+    // チャプター
+    // タイトルのないセクションはファイル名をタイトルにする
+    let titles = sections
+        .iter()
+        .filter(|section| section.video.is_some())
+        .map(|section| section.chapter_title())
+        .collect::<Vec<String>>();
+    let chapters = chapter::create_vec(&titles, &durations, &transitions);
+    let chapters_file = match chapter::write(&output_file, &chapters).await {
+        Ok(chapters_file) => chapters_file,
+        Err(e) => {
+            return Err(format!("chapter Error: {}", e));
+        }
+    };
+
+    // 生成した成果物の一覧
+    let tools = manifest::Tools::detect().await;
+    let manifest = manifest::Manifest::new(
+        sections,
+        &transitions,
+        &output_file,
+        Some(&chapters_file),
+        profile,
+        tools,
+    );
+    match manifest.save() {
+        Ok(manifest_file) => {
+            info!("manifest saved: {:?}", manifest_file);
+        }
+        Err(e) => {
+            return Err(format!("manifest Error: {}", e));
         }
     };
 
-    info!("success: {:?}", output_file);
+    Ok(output_file)
 }
 
 fn init() {
@@ -0,0 +1,196 @@
+use log::{info, warn};
+
+use crate::slide::transition;
+
+// 最終出力の動画のチャプター
+// セクションごとに一つのチャプターとし、MP4のチャプターとYouTubeの概要欄の形式で出力する
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    // 秒
+    pub start: f64,
+    pub end: f64,
+}
+
+// YouTubeのチャプターの条件
+const YOUTUBE_MIN_CHAPTERS: usize = 3;
+const YOUTUBE_MIN_DURATION: f64 = 10.0;
+
+// セクションのタイトルと長さからチャプターを生成する
+// transitions: 各境界のトランジション(連結時と同じもの)
+pub fn create_vec(
+    titles: &[String],
+    durations: &[f64],
+    transitions: &[Option<transition::Transition>],
+) -> Vec<Chapter> {
+    let starts = transition::start_times(durations, transitions);
+    let total =
+        starts.last().copied().unwrap_or_default() + durations.last().copied().unwrap_or_default();
+
+    titles
+        .iter()
+        .zip(starts.iter())
+        .enumerate()
+        .map(|(i, (title, start))| Chapter {
+            title: title.clone(),
+            start: *start,
+            end: starts.get(i + 1).copied().unwrap_or(total),
+        })
+        .collect()
+}
+
+// FFMETADATAのエスケープ
+fn escape_metadata(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// ffmpegのメタデータファイル(FFMETADATA)
+pub fn to_ffmetadata(chapters: &[Chapter]) -> String {
+    let mut text = ";FFMETADATA1\n".to_string();
+    for chapter in chapters {
+        text.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (chapter.start * 1000.0).round() as i64,
+            (chapter.end * 1000.0).round() as i64,
+            escape_metadata(&chapter.title)
+        ));
+    }
+    text
+}
+
+// YouTubeのチャプターの時刻
+// 1時間以上の動画は h:mm:ss、それ以外は mm:ss
+fn youtube_time(seconds: f64, with_hours: bool) -> String {
+    let seconds = seconds.max(0.0).floor() as u64;
+    let (h, m, s) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    if with_hours {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{:02}:{:02}", m, s)
+    }
+}
+
+// YouTubeの概要欄に貼り付けるチャプター(00:00 タイトル)
+pub fn to_youtube(chapters: &[Chapter]) -> String {
+    let with_hours = chapters.last().is_some_and(|chapter| chapter.end >= 3600.0);
+    chapters
+        .iter()
+        .map(|chapter| {
+            format!(
+                "{} {}\n",
+                youtube_time(chapter.start, with_hours),
+                chapter.title.replace('\n', " ")
+            )
+        })
+        .collect()
+}
+
+// YouTubeでチャプターとして認識されない場合の理由
+pub fn youtube_warnings(chapters: &[Chapter]) -> Vec<String> {
+    let mut warnings = vec![];
+    if chapters.len() < YOUTUBE_MIN_CHAPTERS {
+        warnings.push(format!(
+            "youtube requires at least {} chapters: {}",
+            YOUTUBE_MIN_CHAPTERS,
+            chapters.len()
+        ));
+    }
+    for chapter in chapters {
+        if chapter.end - chapter.start < YOUTUBE_MIN_DURATION {
+            warnings.push(format!(
+                "youtube requires chapters of at least {} seconds: {} ({:.1}s)",
+                YOUTUBE_MIN_DURATION,
+                chapter.title,
+                chapter.end - chapter.start
+            ));
+        }
+    }
+    warnings
+}
+
+// チャプターを動画に書き込み、YouTube形式のテキストを出力する
+// テキストは動画と同じ場所の *.chapters.txt
+// 映像と音声はコピーする
+pub async fn write(filepath: &str, chapters: &[Chapter]) -> Result<String, String> {
+    let metadata_filepath = format!("{}.ffmetadata.txt", filepath);
+    std::fs::write(&metadata_filepath, to_ffmetadata(chapters)).map_err(|e| e.to_string())?;
+
+    let tmp_filepath = format!("{}.chapters.mp4", filepath);
+    let output = std::process::Command::new("ffmpeg")
+        .args([
+            "-hide_banner",
+            "-y",
+            "-i",
+            filepath,
+            "-i",
+            metadata_filepath.as_str(),
+            "-map",
+            "0",
+            "-map_metadata",
+            "1",
+            "-map_chapters",
+            "1",
+            "-c",
+            "copy",
+            tmp_filepath.as_str(),
+        ])
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("failed write chapters: {:?}", output));
+    }
+    std::fs::rename(&tmp_filepath, filepath).map_err(|e| e.to_string())?;
+    std::fs::remove_file(&metadata_filepath).map_err(|e| e.to_string())?;
+
+    let youtube_filepath = std::path::Path::new(filepath)
+        .with_extension("chapters.txt")
+        .to_string_lossy()
+        .into_owned();
+    std::fs::write(&youtube_filepath, to_youtube(chapters)).map_err(|e| e.to_string())?;
+    for warning in youtube_warnings(chapters) {
+        warn!("{}", warning);
+    }
+    info!("chapters written: {}", youtube_filepath);
+
+    Ok(youtube_filepath)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chapters() {
+        let fade = transition::Transition::parse("fade:1").unwrap();
+        let titles = vec![
+            "はじめに".to_string(),
+            "a=b; #1".to_string(),
+            "まとめ".to_string(),
+        ];
+        let chapters = create_vec(&titles, &[65.5, 4.0, 3.0], &[fade, None]);
+        assert_eq!(chapters[1].start, 64.5);
+        assert_eq!(chapters[1].end, 68.5);
+        assert_eq!(chapters[2].end, 71.5);
+
+        let metadata = to_ffmetadata(&chapters);
+        assert!(
+            metadata.starts_with(";FFMETADATA1\n[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=64500\n")
+        );
+        assert!(metadata.contains("title=a\\=b\\; \\#1\n"));
+
+        assert_eq!(
+            to_youtube(&chapters),
+            "00:00 はじめに\n01:04 a=b; #1\n01:08 まとめ\n"
+        );
+        assert_eq!(youtube_time(3725.0, true), "1:02:05");
+        assert_eq!(youtube_warnings(&chapters).len(), 2);
+    }
+}
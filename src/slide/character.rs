@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::slide::utils;
+
+// 話者ごとのキャラクター(立ち絵)表示
+// 口の開閉を音声の振幅に合わせて切り替える
+
+// キャラクターの画像セット
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Avatar {
+    // 口を開いた画像(透過PNG)
+    pub mouth_open: String,
+    // 口を閉じた画像(透過PNG)
+    pub mouth_closed: String,
+    // まばたき用の画像(口の画像と同じ大きさで、閉じた目を同じ位置に描いた透過PNG)
+    // 口の画像と同じ高さに拡大・縮小し、同じ位置に重ねる
+    pub blink: Option<String>,
+    // 表示位置(ffmpegのoverlayの式)。未指定の場合は右下
+    pub x: Option<String>,
+    pub y: Option<String>,
+    // 表示する高さ(px)
+    pub height: Option<i32>,
+    // 口を開く振幅のしきい値(0.0〜1.0)
+    pub threshold: Option<f64>,
+}
+
+// ボイスIDごとのキャラクター
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    avatars: HashMap<i32, Avatar>,
+}
+
+impl Registry {
+    // 環境変数DEFAULT_CHARACTER_FILE_PATHのJSONを読み込む
+    // 例) { "2": { "mouth_open": "...", "mouth_closed": "..." } }
+    // 未指定の場合はキャラクターなし
+    pub fn from_env() -> Result<Registry, String> {
+        match std::env::var("DEFAULT_CHARACTER_FILE_PATH") {
+            Ok(path) if !path.is_empty() => Registry::from_file(&path),
+            _ => Ok(Registry::default()),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Registry, String> {
+        let avatars = utils::read_voice_map(path, "characters")?;
+        Ok(Registry { avatars })
+    }
+
+    pub fn get(&self, voice_id: i32) -> Option<&Avatar> {
+        self.avatars.get(&voice_id)
+    }
+}
+
+// 動画に重ねるキャラクター
+// 口を開く時間帯(秒)を持つ
+#[derive(Debug, Clone, Serialize)]
+pub struct Overlay {
+    pub avatar: Avatar,
+    pub mouth_open: Vec<(f64, f64)>,
+}
+
+impl Overlay {
+    // 音声ファイルの振幅から口を開く時間帯を求める
+    pub fn from_wav(avatar: &Avatar, wav_filepath: &str, fps: i32) -> Result<Overlay, String> {
+        let (sample_rate, samples) = read_wav(wav_filepath)?;
+        let threshold = avatar.threshold.unwrap_or(0.02);
+        Ok(Overlay {
+            avatar: avatar.clone(),
+            mouth_open: mouth_intervals(&samples, sample_rate, fps, threshold),
+        })
+    }
+
+    // ffmpegに追加する入力ファイル(口閉じ、口開き、まばたきの順)
+    pub fn inputs(&self) -> Vec<String> {
+        let mut inputs = vec![
+            self.avatar.mouth_closed.clone(),
+            self.avatar.mouth_open.clone(),
+        ];
+        if let Some(blink) = self.avatar.blink.as_ref() {
+            inputs.push(blink.clone());
+        }
+        inputs
+    }
+
+    // キャラクターを重ねるフィルタ
+    // first_input: inputs()の先頭の入力番号
+    // offset: 口の時間帯に加算する秒数(セクション単位で生成する場合のパートの開始位置)
+    // window: キャラクターを表示する時間帯。Noneの場合は常に表示
+    pub fn create_filter(
+        &self,
+        input_label: &str,
+        output_label: &str,
+        first_input: usize,
+        offset: f64,
+        window: Option<(f64, f64)>,
+    ) -> String {
+        let height = self.avatar.height.unwrap_or(480);
+        let x = self.avatar.x.as_deref().unwrap_or("W-w-40");
+        let y = self.avatar.y.as_deref().unwrap_or("H-h");
+        let label = format!("ch{}", first_input);
+        let window_enable = match window {
+            Some((start, end)) => format!("between(t,{start},{end})"),
+            None => "1".to_string(),
+        };
+        let mouth_enable = if self.mouth_open.is_empty() {
+            "0".to_string()
+        } else {
+            self.mouth_open
+                .iter()
+                .map(|(start, end)| format!("between(t,{},{})", start + offset, end + offset))
+                .collect::<Vec<String>>()
+                .join("+")
+        };
+
+        let mut filters = vec![
+            format!("[{first_input}:v]format=rgba,scale=-1:{height}[{label}c];"),
+            format!(
+                "[{}:v]format=rgba,scale=-1:{height}[{label}o];",
+                first_input + 1
+            ),
+            format!(
+                "{input_label}[{label}c]overlay=x={x}:y={y}:shortest=1:enable='{window_enable}'[{label}1];"
+            ),
+        ];
+
+        let mouth_label = if self.avatar.blink.is_some() {
+            format!("[{label}2]")
+        } else {
+            output_label.to_string()
+        };
+        filters.push(format!(
+            "[{label}1][{label}o]overlay=x={x}:y={y}:shortest=1:enable='({mouth_enable})*{window_enable}'{mouth_label}"
+        ));
+
+        // まばたき: 4秒ごとに0.15秒目を閉じる
+        if self.avatar.blink.is_some() {
+            filters.push(format!(
+                ";[{}:v]format=rgba,scale=-1:{height}[{label}b];",
+                first_input + 2
+            ));
+            filters.push(format!(
+                "{mouth_label}[{label}b]overlay=x={x}:y={y}:shortest=1:enable='lt(mod(t,4),0.15)*{window_enable}'{output_label}"
+            ));
+        }
+
+        filters.join("")
+    }
+}
+
+// WAVファイル(16bit PCM)を読み込む
+// 複数チャンネルの場合は先頭のチャンネルのみを返す
+pub fn read_wav(filepath: &str) -> Result<(u32, Vec<i16>), String> {
+    let bytes = std::fs::read(filepath).map_err(|e| e.to_string())?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(format!("not a wav file: {}", filepath));
+    }
+
+    let mut sample_rate = 0;
+    let mut channels = 1;
+    let mut bits_per_sample = 16;
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let id = &bytes[position..position + 4];
+        let size =
+            u32::from_le_bytes(bytes[position + 4..position + 8].try_into().unwrap()) as usize;
+        let body = &bytes[position + 8..(position + 8 + size).min(bytes.len())];
+
+        if id == b"fmt " && body.len() >= 16 {
+            channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+            sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+            bits_per_sample = u16::from_le_bytes([body[14], body[15]]);
+        } else if id == b"data" {
+            if bits_per_sample != 16 {
+                return Err(format!("unsupported bits per sample: {}", bits_per_sample));
+            }
+            let samples = body
+                .chunks_exact(2 * channels.max(1))
+                .map(|frame| i16::from_le_bytes([frame[0], frame[1]]))
+                .collect();
+            return Ok((sample_rate, samples));
+        }
+
+        // チャンクは2バイト境界に揃えられる
+        position += 8 + size + (size % 2);
+    }
+
+    Err(format!("data chunk not found: {}", filepath))
+}
+
+// 振幅から口を開く時間帯(秒)を求める
+// フレームごとのRMSがしきい値を超えたら口を開く
+// 1フレームだけの開閉はちらつくため無視する
+pub fn mouth_intervals(
+    samples: &[i16],
+    sample_rate: u32,
+    fps: i32,
+    threshold: f64,
+) -> Vec<(f64, f64)> {
+    let frame_samples = (sample_rate as usize / fps.max(1) as usize).max(1);
+    let frame_time = |frame: usize| frame as f64 / fps as f64;
+
+    let mut open = samples
+        .chunks(frame_samples)
+        .map(|frame| {
+            let sum = frame
+                .iter()
+                .map(|sample| (*sample as f64 / i16::MAX as f64).powi(2))
+                .sum::<f64>();
+            (sum / frame.len() as f64).sqrt() > threshold
+        })
+        .collect::<Vec<bool>>();
+
+    // 前後と異なる1フレームだけの状態を前後に合わせる
+    for i in 1..open.len().saturating_sub(1) {
+        if open[i - 1] == open[i + 1] && open[i] != open[i - 1] {
+            open[i] = open[i - 1];
+        }
+    }
+
+    let mut intervals = vec![];
+    let mut start = None;
+    for (i, is_open) in open.iter().enumerate() {
+        match (is_open, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                intervals.push((frame_time(s), frame_time(i)));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        intervals.push((frame_time(s), frame_time(open.len())));
+    }
+
+    intervals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_filter() {
+        let mut overlay = Overlay {
+            avatar: Avatar {
+                mouth_open: "open.png".to_string(),
+                mouth_closed: "closed.png".to_string(),
+                blink: None,
+                x: None,
+                y: None,
+                height: Some(400),
+                threshold: None,
+            },
+            mouth_open: vec![(0.5, 1.0)],
+        };
+        assert_eq!(overlay.inputs(), vec!["closed.png", "open.png"]);
+        assert_eq!(
+            overlay.create_filter("[bg]", "[out]", 2, 1.0, Some((1.0, 3.0))),
+            "[2:v]format=rgba,scale=-1:400[ch2c];\
+            [3:v]format=rgba,scale=-1:400[ch2o];\
+            [bg][ch2c]overlay=x=W-w-40:y=H-h:shortest=1:enable='between(t,1,3)'[ch21];\
+            [ch21][ch2o]overlay=x=W-w-40:y=H-h:shortest=1:enable='(between(t,1.5,2))*between(t,1,3)'[out]"
+        );
+
+        // まばたきの画像は口の画像と同じ高さ、同じ位置に重ねる
+        overlay.avatar.blink = Some("blink.png".to_string());
+        overlay.mouth_open.clear();
+        let filter = overlay.create_filter("[bg]", "[out]", 1, 0.0, None);
+        assert_eq!(overlay.inputs().len(), 3);
+        assert!(filter.contains("enable='(0)*1'[ch12];"));
+        assert!(filter.ends_with(
+            ";[3:v]format=rgba,scale=-1:400[ch1b];\
+            [ch12][ch1b]overlay=x=W-w-40:y=H-h:shortest=1:enable='lt(mod(t,4),0.15)*1'[out]"
+        ));
+    }
+
+    #[test]
+    fn test_mouth_intervals() {
+        // 10Hz、1フレーム100サンプル
+        // 無音2フレーム、発声3フレーム、無音1フレーム(無視される)、発声1フレーム、無音3フレーム
+        let pattern = [0, 0, 1, 1, 1, 0, 1, 0, 0, 0];
+        let samples = pattern
+            .iter()
+            .flat_map(|voiced| vec![if *voiced == 1 { 10000 } else { 0 }; 100])
+            .collect::<Vec<i16>>();
+
+        let intervals = mouth_intervals(&samples, 1000, 10, 0.02);
+        assert_eq!(intervals, vec![(0.2, 0.7)]);
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::slide::{loudness, utils, voice};
+
+// 生成の途中経過(ジョブの状態)
+// 完了した音声と動画を記録し、失敗後に同じスクリプトで再実行した場合は続きから生成する
+// 状態ファイルは DEFAULT_OUTPUT_VIDEO_FILE_DIR/job-{スクリプトのハッシュ}.json
+// すべての出力が完了したら削除する
+
+// 記録した音声
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoiceEntry {
+    pub voice_id: Option<i32>,
+    pub filepath: String,
+    pub duration_ms: i64,
+    pub query_filepath: Option<String>,
+    // ラウドネス正規化の計測値
+    #[serde(default)]
+    pub loudness: Option<loudness::Measurement>,
+}
+
+// 記録した動画
+// fingerprint: 生成に使った入力のハッシュ。一致しない場合は生成し直す
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputEntry {
+    pub fingerprint: String,
+    pub filepath: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Job {
+    // 状態ファイル。Noneの場合は記録しない
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    // contents key -> 音声
+    #[serde(default)]
+    pub voices: HashMap<String, VoiceEntry>,
+    // テキストごと、セクションごとの動画
+    #[serde(default)]
+    pub outputs: HashMap<String, OutputEntry>,
+}
+
+// 値のハッシュ(キャッシュのキー)
+// Rustのバージョンによらず同じ値になるよう、JSONにシリアライズした値のSHA-256を使う
+pub fn fingerprint<T: Serialize + ?Sized>(value: &T) -> String {
+    let mut hasher = Sha256::new();
+    // キャッシュのキーに使う値はすべてJSONにできる
+    serde_json::to_writer(&mut hasher, value).unwrap();
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+impl Job {
+    // 記録しないジョブ
+    pub fn disabled() -> Job {
+        Job::default()
+    }
+
+    // スクリプトのジョブの状態を読み込む
+    // DEFAULT_RESUME=off の場合は記録せず、最初から生成する
+    pub fn load(resource_text: &Path) -> Result<Job, String> {
+        match std::env::var("DEFAULT_RESUME")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "on" | "true" => {}
+            "off" | "false" => return Ok(Job::disabled()),
+            other => return Err(format!("unknown resume mode: {}", other)),
+        }
+
+        let current_dir = std::env::current_dir().map_err(|e| e.to_string())?;
+        let resource_text = utils::absolute_path(resource_text, &current_dir);
+        let path = utils::target_path_from_env("DEFAULT_OUTPUT_VIDEO_FILE_DIR").join(format!(
+            "job-{}.json",
+            fingerprint(&resource_text.to_string_lossy())
+        ));
+
+        let mut job = match std::fs::read_to_string(&path) {
+            Ok(text) => match serde_json::from_str::<Job>(&text) {
+                Ok(job) => {
+                    info!(
+                        "resume job: {} ({} voices, {} videos)",
+                        path.display(),
+                        job.voices.len(),
+                        job.outputs.len()
+                    );
+                    job
+                }
+                Err(e) => {
+                    warn!("job state ignored: {}: {}", path.display(), e);
+                    Job::default()
+                }
+            },
+            Err(_) => Job::default(),
+        };
+        job.path = Some(path);
+
+        Ok(job)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| e.to_string())
+    }
+
+    // 完了した音声。ファイルがない場合はNone
+    pub fn voice(&self, key: &str) -> Option<voice::Data> {
+        let entry = self.voices.get(key)?;
+        if !Path::new(&entry.filepath).exists() {
+            return None;
+        }
+
+        let mut voice_data = voice::Data::new(entry.voice_id, &entry.filepath);
+        voice_data.duration = chrono::TimeDelta::milliseconds(entry.duration_ms);
+        voice_data.query_filepath = entry
+            .query_filepath
+            .clone()
+            .filter(|query_filepath| Path::new(query_filepath).exists());
+        voice_data.loudness = entry.loudness.clone();
+        Some(voice_data)
+    }
+
+    pub fn set_voice(&mut self, key: &str, voice_data: &voice::Data) -> Result<(), String> {
+        self.voices.insert(
+            key.to_string(),
+            VoiceEntry {
+                voice_id: voice_data.voice_id,
+                filepath: voice_data.filepath.clone(),
+                duration_ms: voice_data.duration.num_milliseconds(),
+                query_filepath: voice_data.query_filepath.clone(),
+                loudness: voice_data.loudness.clone(),
+            },
+        );
+        self.save()
+    }
+
+    // 完了した動画。入力が変わった場合やファイルがない場合はNone
+    pub fn output(&self, key: &str, fingerprint: &str) -> Option<String> {
+        let entry = self.outputs.get(key)?;
+        if entry.fingerprint != fingerprint || !Path::new(&entry.filepath).exists() {
+            return None;
+        }
+        Some(entry.filepath.clone())
+    }
+
+    pub fn set_output(
+        &mut self,
+        key: &str,
+        fingerprint: &str,
+        filepath: &str,
+    ) -> Result<(), String> {
+        self.outputs.insert(
+            key.to_string(),
+            OutputEntry {
+                fingerprint: fingerprint.to_string(),
+                filepath: filepath.to_string(),
+            },
+        );
+        self.save()
+    }
+
+    // すべての出力が完了したら状態ファイルを削除する
+    pub fn finish(&self) -> Result<(), String> {
+        match self.path.as_ref() {
+            Some(path) if path.exists() => std::fs::remove_file(path).map_err(|e| e.to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("job.json");
+        let wav = dir.join("a.wav").to_string_lossy().into_owned();
+        let part = dir.join("a.mp4").to_string_lossy().into_owned();
+
+        let mut job = Job {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        let mut voice_data = voice::Data::new(Some(3), &wav);
+        voice_data.duration = chrono::TimeDelta::milliseconds(1500);
+        voice_data.loudness = Some(loudness::Measurement {
+            input_i: -27.6,
+            input_tp: -4.5,
+            input_lra: 6.2,
+            input_thresh: -38.0,
+            target_offset: 0.3,
+        });
+        job.set_voice("a", &voice_data).unwrap();
+        job.set_output("a-part", "f1", &part).unwrap();
+
+        // ファイルがない場合は再利用しない
+        assert!(job.voice("a").is_none());
+        assert!(job.output("a-part", "f1").is_none());
+
+        std::fs::write(&wav, "").unwrap();
+        std::fs::write(&part, "").unwrap();
+        let mut loaded =
+            serde_json::from_str::<Job>(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        loaded.path = Some(path.clone());
+        let voice_data = loaded.voice("a").unwrap();
+        assert_eq!(voice_data.voice_id, Some(3));
+        assert_eq!(voice_data.duration.num_milliseconds(), 1500);
+        // 正規化の計測値も引き継ぐ
+        assert_eq!(
+            voice_data.loudness.map(|measured| measured.input_i),
+            Some(-27.6)
+        );
+        assert_eq!(loaded.output("a-part", "f1"), Some(part));
+        // 入力が変わった場合は生成し直す
+        assert!(loaded.output("a-part", "f2").is_none());
+
+        loaded.finish().unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        // JSONのSHA-256なので、Rustのバージョンが変わっても同じ値になる
+        assert_eq!(fingerprint(&("a", 1)), "135f17a475a61afd");
+        assert_ne!(fingerprint(&("a", 1)), fingerprint(&("a", 2)));
+    }
+}
@@ -0,0 +1,256 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+
+// EBU R128 に基づくラウドネス正規化
+// ffmpegのloudnormフィルタを2パスで実行する
+// 1パス目で計測、2パス目で計測値を与えて線形に補正する
+
+// 正規化を適用する対象
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Mode {
+    // 正規化しない
+    Off,
+    // 音声ファイルごとに正規化
+    Clip,
+    // 最終出力の動画に対して正規化
+    Mix,
+}
+
+impl Mode {
+    pub fn from_env() -> Mode {
+        match std::env::var("DEFAULT_LOUDNORM_MODE")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "clip" => Mode::Clip,
+            "mix" => Mode::Mix,
+            _ => Mode::Off,
+        }
+    }
+}
+
+// 正規化の目標値
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Target {
+    // 統合ラウドネス(LUFS)
+    pub i: f64,
+    // トゥルーピーク(dBTP)
+    pub tp: f64,
+    // ラウドネスレンジ(LU)
+    pub lra: f64,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target {
+            i: -16.0,
+            tp: -1.5,
+            lra: 11.0,
+        }
+    }
+}
+
+impl Target {
+    pub fn from_env() -> Target {
+        let default = Target::default();
+        let read = |key: &str, default: f64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(default)
+        };
+
+        Target {
+            i: read("DEFAULT_LOUDNORM_TARGET_LUFS", default.i),
+            tp: read("DEFAULT_LOUDNORM_TRUE_PEAK", default.tp),
+            lra: read("DEFAULT_LOUDNORM_LRA", default.lra),
+        }
+    }
+
+    fn filter(&self) -> String {
+        format!("loudnorm=I={}:TP={}:LRA={}", self.i, self.tp, self.lra)
+    }
+}
+
+// loudnormの計測値
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub input_i: f64,
+    pub input_tp: f64,
+    pub input_lra: f64,
+    pub input_thresh: f64,
+    pub target_offset: f64,
+}
+
+impl Measurement {
+    // ffmpegの標準エラー出力からloudnormのJSONブロックを取り出す
+    // JSONはログの末尾に出力されるため、最後の{...}を対象とする
+    pub fn parse(stderr: &str) -> Result<Measurement, String> {
+        let start = stderr
+            .rfind('{')
+            .ok_or("loudnorm json not found".to_string())?;
+        let end = stderr[start..]
+            .find('}')
+            .map(|end| start + end + 1)
+            .ok_or("loudnorm json not closed".to_string())?;
+
+        let json: serde_json::Value =
+            serde_json::from_str(&stderr[start..end]).map_err(|e| e.to_string())?;
+
+        // 値は文字列として出力される(例: "input_i" : "-27.61")
+        let value = |key: &str| -> Result<f64, String> {
+            json.get(key)
+                .and_then(|v| v.as_str())
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .ok_or(format!("loudnorm value not found: {}", key))
+        };
+
+        Ok(Measurement {
+            input_i: value("input_i")?,
+            input_tp: value("input_tp")?,
+            input_lra: value("input_lra")?,
+            input_thresh: value("input_thresh")?,
+            target_offset: value("target_offset")?,
+        })
+    }
+}
+
+// 1パス目: 入力のラウドネスを計測する
+pub async fn measure(input_filepath: &str, target: &Target) -> Result<Measurement, String> {
+    let filter = format!("{}:print_format=json", target.filter());
+    let output = std::process::Command::new("ffmpeg")
+        .args([
+            "-hide_banner",
+            "-nostats",
+            "-i",
+            input_filepath,
+            "-af",
+            filter.as_str(),
+            "-f",
+            "null",
+            "-",
+        ])
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(format!("failed loudnorm measure: {:?}", output));
+    }
+
+    Measurement::parse(&String::from_utf8_lossy(&output.stderr))
+}
+
+// 2パス目: 計測値を与えて正規化する
+// extra_argsは出力側のオプション(コーデック指定など)
+async fn apply(
+    input_filepath: &str,
+    output_filepath: &str,
+    target: &Target,
+    measured: &Measurement,
+    extra_args: &[&str],
+) -> Result<(), String> {
+    let filter = format!(
+        "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+        target.filter(),
+        measured.input_i,
+        measured.input_tp,
+        measured.input_lra,
+        measured.input_thresh,
+        measured.target_offset,
+    );
+
+    let mut args = vec![
+        "-hide_banner",
+        "-y",
+        "-i",
+        input_filepath,
+        "-af",
+        filter.as_str(),
+    ];
+    args.extend_from_slice(extra_args);
+    args.push(output_filepath);
+
+    let output = std::process::Command::new("ffmpeg")
+        .args(args)
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("failed loudnorm apply: {:?}", output))
+    }
+}
+
+// 音声ファイルを正規化し、元のファイルを置き換える
+// 計測値を返す
+pub async fn normalize_clip(filepath: &str, target: &Target) -> Result<Measurement, String> {
+    let measured = measure(filepath, target).await?;
+    info!("loudnorm measured: {} {:?}", filepath, measured);
+
+    let tmp_filepath = format!("{}.loudnorm.wav", filepath);
+    // loudnormは内部で192kHzに変換するため、Voicevoxのデフォルトのサンプルレートに戻す
+    apply(
+        filepath,
+        &tmp_filepath,
+        target,
+        &measured,
+        &["-ar", "24000"],
+    )
+    .await?;
+    std::fs::rename(&tmp_filepath, filepath).map_err(|e| e.to_string())?;
+
+    Ok(measured)
+}
+
+// 動画ファイルの音声を正規化し、元のファイルを置き換える
+// 映像はコピーする
+pub async fn normalize_video(filepath: &str, target: &Target) -> Result<Measurement, String> {
+    let measured = measure(filepath, target).await?;
+    info!("loudnorm measured: {} {:?}", filepath, measured);
+
+    let tmp_filepath = format!("{}.loudnorm.mp4", filepath);
+    apply(
+        filepath,
+        &tmp_filepath,
+        target,
+        &measured,
+        &["-c:v", "copy", "-c:a", "aac", "-ar", "48000"],
+    )
+    .await?;
+    std::fs::rename(&tmp_filepath, filepath).map_err(|e| e.to_string())?;
+
+    Ok(measured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_measurement() {
+        let stderr = r#"
+[Parsed_loudnorm_0 @ 0x5581]
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-4.47",
+	"input_lra" : "18.06",
+	"input_thresh" : "-39.20",
+	"output_i" : "-16.58",
+	"output_tp" : "-1.50",
+	"output_lra" : "14.78",
+	"output_thresh" : "-27.71",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.58"
+}
+"#;
+
+        let measured = Measurement::parse(stderr).unwrap();
+        assert_eq!(measured.input_i, -27.61);
+        assert_eq!(measured.input_tp, -4.47);
+        assert_eq!(measured.target_offset, 0.58);
+
+        assert!(Measurement::parse("no json").is_err());
+    }
+}
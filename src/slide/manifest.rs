@@ -0,0 +1,262 @@
+use serde::Serialize;
+
+use crate::slide::{profile, section::Section, transition, voicevox};
+
+// 生成した成果物の一覧(マニフェスト)
+// 最終出力の動画と同じ場所に *.manifest.json として保存する
+// 後段のツールでの素材の参照や、生成結果の比較に使う
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Manifest {
+    pub created_at: String,
+    // スクリプトファイル
+    pub resource: Option<String>,
+    pub profile: Option<String>,
+    pub output: String,
+    // YouTube形式のチャプターのテキスト
+    pub chapters: Option<String>,
+    // 秒
+    pub duration: f64,
+    pub tools: Tools,
+    pub sections: Vec<SectionEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Tools {
+    pub slide_with_voice: String,
+    // ffmpeg -version の1行目
+    pub ffmpeg: Option<String>,
+    pub voicevox: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionEntry {
+    pub title: Option<String>,
+    pub filename: String,
+    // ヘッダーを記述したファイルと行番号
+    pub source: Option<String>,
+    // 最終出力での開始時刻(秒)
+    pub start: f64,
+    pub duration: f64,
+    pub transition: Option<TransitionEntry>,
+    pub video: Option<String>,
+    pub contents: Vec<ContentEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransitionEntry {
+    pub kind: String,
+    pub duration: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContentEntry {
+    pub key: String,
+    pub text: String,
+    pub voice_id: Option<i32>,
+    // 音声ファイルと音声合成クエリ
+    pub voice: Option<String>,
+    pub query: Option<String>,
+    // 最終出力での開始時刻(秒)
+    pub start: f64,
+    pub duration: f64,
+    // テキストごとの動画(テキストごとに生成した場合のみ)
+    pub part: Option<String>,
+}
+
+impl Manifest {
+    // sections: 動画を生成したセクション
+    // transitions: 各境界のトランジション(連結時と同じもの)
+    pub fn new(
+        sections: &[Section],
+        transitions: &[Option<transition::Transition>],
+        output: &str,
+        chapters: Option<&str>,
+        profile: Option<&profile::Profile>,
+        tools: Tools,
+    ) -> Manifest {
+        let sections = sections
+            .iter()
+            .filter(|section| section.video.is_some())
+            .collect::<Vec<&Section>>();
+        let durations = sections
+            .iter()
+            .map(|section| section.duration())
+            .collect::<Vec<f64>>();
+        let starts = transition::start_times(&durations, transitions);
+
+        let sections = sections
+            .iter()
+            .zip(starts.iter().zip(durations.iter()))
+            .enumerate()
+            .map(|(i, (section, (start, duration)))| {
+                let transition = match i {
+                    0 => None,
+                    _ => transitions.get(i - 1).copied().flatten(),
+                };
+                SectionEntry {
+                    title: section.title.clone(),
+                    filename: section.filename.clone(),
+                    source: section.source.clone(),
+                    start: *start,
+                    duration: *duration,
+                    transition: transition.map(|transition| TransitionEntry {
+                        kind: format!("{:?}", transition.kind).to_lowercase(),
+                        duration: transition.duration,
+                    }),
+                    video: section.video.clone(),
+                    contents: content_entries(section, *start),
+                }
+            })
+            .collect::<Vec<SectionEntry>>();
+        let duration = sections
+            .last()
+            .map(|section| section.start + section.duration)
+            .unwrap_or_default();
+
+        Manifest {
+            created_at: chrono::Local::now().to_rfc3339(),
+            resource: std::env::var("DEFAULT_RESOURCE_FILE_PATH").ok(),
+            profile: profile.map(|profile| profile.name.clone()),
+            output: output.to_string(),
+            chapters: chapters.map(|chapters| chapters.to_string()),
+            duration,
+            tools,
+            sections,
+        }
+    }
+
+    // 出力ファイルと同じ場所に保存し、保存したパスを返す
+    pub fn save(&self) -> Result<String, String> {
+        let filepath = std::path::Path::new(&self.output)
+            .with_extension("manifest.json")
+            .to_string_lossy()
+            .into_owned();
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&filepath, json).map_err(|e| e.to_string())?;
+        Ok(filepath)
+    }
+}
+
+// テキストごとの音声、動画と開始時刻
+fn content_entries(section: &Section, section_start: f64) -> Vec<ContentEntry> {
+    let mut start = section_start;
+    section
+        .contents
+        .iter()
+        .map(|content| {
+            let voice_data = section.voices.get(&content.key);
+            let duration = voice_data
+                .map(|voice_data| voice_data.duration.num_milliseconds() as f64 / 1000.0)
+                .unwrap_or_default();
+            let entry = ContentEntry {
+                key: content.key.clone(),
+                text: content.text.clone(),
+                // 指定がない場合はデフォルトのボイスID
+                voice_id: content.voice_id.or_else(|| {
+                    std::env::var("DEFAULT_VOICEVOX_VOICE_ID")
+                        .ok()
+                        .and_then(|voice_id| voice_id.parse::<i32>().ok())
+                }),
+                voice: voice_data.map(|voice_data| voice_data.filepath.clone()),
+                query: voice_data.and_then(|voice_data| voice_data.query_filepath.clone()),
+                start,
+                duration,
+                part: section.parts.get(&content.key).cloned(),
+            };
+            start += duration;
+            entry
+        })
+        .collect()
+}
+
+impl Tools {
+    // ffmpegとVOICEVOXのバージョンを取得する
+    // 取得できない場合はNone
+    pub async fn detect() -> Tools {
+        let ffmpeg = std::process::Command::new("ffmpeg")
+            .arg("-version")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .next()
+                    .map(|line| line.trim().to_string())
+            });
+        let voicevox = match std::env::var("DEFAULT_VOICEVOX_SERVER_URL") {
+            // バージョンの取得のために待たない(再試行せず、応答がなければすぐに諦める)
+            Ok(base_path) => match voicevox::Client::with_policy(
+                base_path,
+                voicevox::RetryPolicy {
+                    timeout: std::time::Duration::from_secs(2),
+                    retries: 0,
+                    ..Default::default()
+                },
+            ) {
+                Ok(client) => client.version().await.ok(),
+                Err(_) => None,
+            },
+            Err(_) => None,
+        };
+
+        Tools {
+            slide_with_voice: env!("CARGO_PKG_VERSION").to_string(),
+            ffmpeg,
+            voicevox,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slide::{section::Content, voice};
+
+    fn section(title: &str, durations: &[i64]) -> Section {
+        let mut section = Section::blanc();
+        section.filename = format!("/slides/{}.png", title);
+        section.title = Some(title.to_string());
+        section.video = Some(format!("/output/{}.mp4", title));
+        for (i, duration) in durations.iter().enumerate() {
+            let content = Content::new(Some(3), format!("{}-{}", title, i));
+            let mut voice_data = voice::Data::new(Some(3), &format!("/voice/{}.wav", content.key));
+            voice_data.duration = chrono::TimeDelta::milliseconds(*duration);
+            section
+                .parts
+                .insert(content.key.clone(), format!("/output/{}.mp4", content.key));
+            section.voices.insert(content.key.clone(), voice_data);
+            section.contents.push(content);
+        }
+        section
+    }
+
+    #[test]
+    fn test_manifest() {
+        let fade = transition::Transition::parse("fade:1").unwrap();
+        let mut skipped = section("skipped", &[1000]);
+        skipped.video = None;
+        let sections = vec![section("a", &[2000, 3000]), skipped, section("b", &[4000])];
+        let tools = Tools {
+            slide_with_voice: "0.1.0".to_string(),
+            ffmpeg: None,
+            voicevox: None,
+        };
+        let manifest = Manifest::new(&sections, &[fade], "/output/concat.mp4", None, None, tools);
+
+        assert_eq!(manifest.sections.len(), 2);
+        assert_eq!(manifest.duration, 8.0);
+        let contents = &manifest.sections[0].contents;
+        assert_eq!(contents[1].start, 2.0);
+        assert_eq!(contents[1].voice_id, Some(3));
+        assert!(contents[1].part.is_some());
+        let second = &manifest.sections[1];
+        assert_eq!(second.start, 4.0);
+        assert_eq!(second.contents[0].start, 4.0);
+        assert_eq!(second.transition.as_ref().unwrap().kind, "fade");
+
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["sections"][1]["title"], "b");
+    }
+}
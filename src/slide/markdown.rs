@@ -0,0 +1,356 @@
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::slide::{
+    section::{Content, Section},
+    utils, video_option,
+};
+
+/* Markdown形式のスクリプト
+例）
+<!-- theme=resource/themes/dark.toml -->
+## スライド1のタイトル
+![](C:\path\to\slide1.png)
+<!-- transition=fade:0.5, @2 -->
+これはスライド1の説明です。
+改行は同じ段落として扱います。
+
+> @3 これは別の話者の説明です。
+
+![](C:\path\to\slide2.png)
+- 箇条書きは項目ごとに読み上げます。
+*/
+
+// 見出し(##)または画像の行でセクションを区切る
+// 段落、箇条書きの項目、引用の行がそれぞれ一つのテキストになる
+// HTMLコメントにはセクションの属性(key=value)と話者(@ボイスID)を記述する
+// base_dir: テーマの相対パスの基準となるディレクトリ
+pub fn create_vec(
+    text: &str,
+    theme: video_option::Op,
+    base_dir: &Path,
+) -> Result<Vec<Section>, String> {
+    let mut parser = Parser {
+        sections: vec![],
+        current: None,
+        base_dir: base_dir.to_path_buf(),
+        theme,
+        voice_id: None,
+        paragraph: vec![],
+    };
+
+    let mut in_code = false;
+    let mut comment: Option<String> = None;
+    for line in text.lines() {
+        let line = line.trim();
+
+        // コードブロックは読み上げない
+        if line.starts_with("```") {
+            parser.flush_paragraph();
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+
+        // 複数行のコメント
+        if let Some(mut body) = comment.take() {
+            match line.split_once("-->") {
+                Some((tail, _)) => {
+                    body.push(',');
+                    body.push_str(tail);
+                    parser.apply_comment(&body)?;
+                }
+                None => {
+                    body.push(',');
+                    body.push_str(line);
+                    comment = Some(body);
+                }
+            }
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("<!--") {
+            parser.flush_paragraph();
+            match rest.split_once("-->") {
+                Some((body, _)) => parser.apply_comment(body)?,
+                None => comment = Some(rest.to_string()),
+            }
+            continue;
+        }
+
+        if line.is_empty() {
+            parser.flush_paragraph();
+        } else if let Some(title) = line.strip_prefix("## ") {
+            parser.start_section(Some(title.trim().to_string()), None);
+        } else if line.starts_with('#') {
+            // その他の見出しはドキュメントの構成用として読み上げない
+            parser.flush_paragraph();
+        } else if let Some(path) = image_path(line) {
+            // 見出しの直後の画像は見出しのセクションのスライドとする
+            parser.flush_paragraph();
+            match parser.current.as_mut() {
+                Some(section) if section.filename.is_empty() => {
+                    section.filename = path.to_string();
+                }
+                _ => parser.start_section(None, Some(path.to_string())),
+            }
+        } else if let Some(quote) = line.strip_prefix('>') {
+            parser.flush_paragraph();
+            parser.push_content(quote.trim());
+        } else if let Some(item) = list_item(line) {
+            parser.flush_paragraph();
+            parser.push_content(item);
+        } else {
+            parser.paragraph.push(line.to_string());
+        }
+    }
+    parser.flush_paragraph();
+    parser.finish_section();
+
+    Ok(parser.sections)
+}
+
+struct Parser {
+    sections: Vec<Section>,
+    current: Option<Section>,
+    // テーマの相対パスの基準となるディレクトリ
+    base_dir: PathBuf,
+    // 以降のセクションに適用するテーマ
+    theme: video_option::Op,
+    // 以降のテキストの話者
+    voice_id: Option<i32>,
+    // 段落の行
+    paragraph: Vec<String>,
+}
+
+impl Parser {
+    fn start_section(&mut self, title: Option<String>, filename: Option<String>) {
+        self.flush_paragraph();
+        self.finish_section();
+
+        let mut section = Section::blanc();
+        section.title = title;
+        section.filename = filename.unwrap_or_default();
+        section.style = self.theme.clone();
+        self.current = Some(section);
+    }
+
+    fn finish_section(&mut self) {
+        if let Some(section) = self.current.take() {
+            if section.filename.is_empty() {
+                warn!(
+                    "section without slide image is skipped: {:?}",
+                    section.title
+                );
+            } else {
+                self.sections.push(section);
+            }
+        }
+    }
+
+    // 段落の行を一つのテキストにする
+    // 日本語の行はそのまま、英語の行は空白でつなぐ
+    fn flush_paragraph(&mut self) {
+        if self.paragraph.is_empty() {
+            return;
+        }
+        let mut text = String::new();
+        for line in std::mem::take(&mut self.paragraph) {
+            if text
+                .chars()
+                .last()
+                .is_some_and(|c| c.is_ascii_alphanumeric())
+            {
+                text.push(' ');
+            }
+            text.push_str(&line);
+        }
+        self.push_content(&text);
+    }
+
+    fn push_content(&mut self, text: &str) {
+        let Some(section) = self.current.as_mut() else {
+            warn!("text before the first section is ignored: {}", text);
+            return;
+        };
+
+        // @ボイスID テキスト の形式の場合はその行だけ話者を変える
+        let (voice_id, text) = match text.strip_prefix('@').and_then(|rest| rest.split_once(' ')) {
+            Some((voice_id, text)) if voice_id.parse::<i32>().is_ok() => {
+                (voice_id.parse::<i32>().ok(), text.trim())
+            }
+            _ => (self.voice_id, text),
+        };
+        let text = strip_inline(text);
+        if !text.is_empty() {
+            section.contents.push(Content::new(voice_id, text));
+        }
+    }
+
+    // コメントの属性を反映する
+    // セクションの前はドキュメント全体のテーマ、セクション内はセクションの属性となる
+    fn apply_comment(&mut self, body: &str) -> Result<(), String> {
+        for attribute in utils::split_attributes(body)
+            .into_iter()
+            .map(|attribute| attribute.trim())
+        {
+            if let Some(voice_id) = attribute.strip_prefix('@') {
+                self.voice_id = match voice_id.trim() {
+                    "" | "default" => None,
+                    voice_id => Some(
+                        voice_id
+                            .parse::<i32>()
+                            .map_err(|e| format!("invalid voice id {}: {}", voice_id, e))?,
+                    ),
+                };
+                continue;
+            }
+
+            let Some((key, value)) = attribute.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_lowercase(), value.trim());
+            match self.current.as_mut() {
+                Some(section) => section.apply_attribute(&key, value, &self.base_dir)?,
+                None if key == "theme" => {
+                    // 環境変数のテーマや先に指定した属性に重ねる
+                    let path = utils::absolute_path(Path::new(value), &self.base_dir);
+                    self.theme.merge(&video_option::Op::from_file(&path)?);
+                }
+                None => {
+                    if !self.theme.set(&key, value)? {
+                        warn!("unknown document attribute: {}={}", key, value);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// ![代替テキスト](パス) の行の画像のパス
+fn image_path(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("![")?;
+    let (_, rest) = rest.split_once("](")?;
+    let path = rest.strip_suffix(')')?;
+    // タイトル付き ![](path "title") の場合はタイトルを除く
+    let path = path.split_once(" \"").map(|(path, _)| path).unwrap_or(path);
+    Some(path.trim().trim_start_matches('<').trim_end_matches('>'))
+}
+
+// 箇条書きの項目
+fn list_item(line: &str) -> Option<&str> {
+    if let Some(item) = line
+        .strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))
+        .or_else(|| line.strip_prefix("+ "))
+    {
+        return Some(item.trim());
+    }
+    let (number, item) = line.split_once(". ")?;
+    (!number.is_empty() && number.chars().all(|c| c.is_ascii_digit())).then(|| item.trim())
+}
+
+// 強調、コード、リンクの記法を取り除く
+fn strip_inline(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        // [テキスト](URL) はテキストのみ
+        if c == '['
+            && let Some((label, tail)) = rest[1..].split_once("](")
+            && let Some((_, tail)) = tail.split_once(')')
+        {
+            result.push_str(label);
+            rest = tail;
+            continue;
+        }
+        if !matches!(c, '*' | '`') && !rest.starts_with("__") {
+            result.push(c);
+        }
+        rest = &rest[if rest.starts_with("__") {
+            2
+        } else {
+            c.len_utf8()
+        }..];
+    }
+
+    result.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_vec() {
+        let text = r#"<!-- font_color=black -->
+# 資料のタイトル
+
+## はじめに
+![表紙](slide1.png)
+<!-- transition=fade:1.0, @2 -->
+これは**最初の**説明です。
+改行は同じ段落です。
+
+> @3 別の話者です。
+
+- 項目1
+- [項目2](https://example.com)
+
+```
+let code = 1;
+```
+
+![](slide2.png)
+<!--
+  @8
+-->
+次のスライドです。
+
+## 画像のないセクション
+読み上げられない
+"#;
+        let sections = create_vec(text, video_option::Op::empty(), Path::new(".")).unwrap();
+        assert_eq!(sections.len(), 2);
+
+        let first = &sections[0];
+        assert_eq!(first.filename, "slide1.png");
+        assert_eq!(first.title.as_deref(), Some("はじめに"));
+        assert!(first.transition.is_some());
+        assert_eq!(first.style.font_color.as_deref(), Some("black"));
+        let contents = first
+            .contents
+            .iter()
+            .map(|content| (content.voice_id, content.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            vec![
+                (Some(2), "これは最初の説明です。改行は同じ段落です。"),
+                (Some(3), "別の話者です。"),
+                (Some(2), "項目1"),
+                (Some(2), "項目2"),
+            ]
+        );
+
+        let second = &sections[1];
+        assert_eq!(second.filename, "slide2.png");
+        assert!(second.title.is_none());
+        assert_eq!(second.contents[0].voice_id, Some(8));
+        assert_eq!(second.contents[0].text, "次のスライドです。");
+
+        // ドキュメントのテーマは環境変数のテーマと先に指定した属性に重ねる
+        let text = "<!-- box_color=black -->\n<!-- theme=resource/themes/dark.toml -->\n![](slide1.png)\n本文\n";
+        let mut theme = video_option::Op::empty();
+        theme.margin_y = Some(80);
+        let sections = create_vec(text, theme, Path::new(env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let style = &sections[0].style;
+        assert_eq!(style.margin_y, Some(80));
+        assert_eq!(style.box_color.as_deref(), Some("black"));
+        assert_eq!(style.background_color.as_deref(), Some("0x222222"));
+    }
+}
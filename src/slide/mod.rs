@@ -1,7 +1,22 @@
+pub mod chapter;
+pub mod character;
 pub mod file;
+pub mod job;
+pub mod loudness;
+pub mod manifest;
+pub mod markdown;
+pub mod pdf;
+pub mod pptx;
+pub mod probe;
+pub mod profile;
+pub mod script;
 pub mod section;
+pub mod speaker;
+pub mod subtitle;
+pub mod transition;
 
 pub mod utils;
 pub mod video;
 pub mod video_option;
 pub mod voice;
+pub mod voicevox;
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+
+use log::info;
+use sha2::{Digest, Sha256};
+
+use crate::slide::utils;
+
+// PDFのページをスライド画像に変換する
+// 例) deck.pdf#3 -> 3ページ目をPNGに変換したファイル
+// deck.pdf#* はすべてのページのスライドに展開する
+
+// PDFのページ指定
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub path: String,
+    // 1から始まるページ番号
+    pub number: u32,
+}
+
+impl Page {
+    // ファイル名がPDFの場合のみページ指定として扱う
+    // ページ番号を省略した場合は1ページ目
+    pub fn parse(filename: &str) -> Result<Option<Page>, String> {
+        let (path, number) = match filename.rsplit_once('#') {
+            Some((path, number)) => (path, Some(number)),
+            None => (filename, None),
+        };
+        if !path.to_lowercase().ends_with(".pdf") {
+            return Ok(None);
+        }
+
+        let number = match number {
+            Some(number) => number
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|number| *number > 0)
+                .ok_or(format!("invalid pdf page: {}", filename))?,
+            None => 1,
+        };
+
+        Ok(Some(Page {
+            path: path.trim().to_string(),
+            number,
+        }))
+    }
+}
+
+// すべてのページを指定したPDFのパス(deck.pdf#*)
+pub fn all_pages(filename: &str) -> Option<&str> {
+    let path = filename.strip_suffix("#*")?;
+    path.to_lowercase().ends_with(".pdf").then_some(path)
+}
+
+// PDFを画像に変換するコマンド
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rasterizer {
+    // poppler-utils
+    Pdftoppm,
+    // MuPDF
+    Mutool,
+}
+
+impl Rasterizer {
+    pub fn from_env() -> Result<Rasterizer, String> {
+        match std::env::var("DEFAULT_PDF_RASTERIZER")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "pdftoppm" => Ok(Rasterizer::Pdftoppm),
+            "mutool" => Ok(Rasterizer::Mutool),
+            other => Err(format!("unknown pdf rasterizer: {}", other)),
+        }
+    }
+
+    // ページ数を取得するコマンド
+    fn info_args(&self, path: &str) -> (&'static str, Vec<String>) {
+        match self {
+            Rasterizer::Pdftoppm => ("pdfinfo", vec![path.to_string()]),
+            Rasterizer::Mutool => ("mutool", vec!["info".to_string(), path.to_string()]),
+        }
+    }
+
+    // output: 出力するPNGファイル
+    fn args(&self, page: &Page, dpi: u32, output: &str) -> (&'static str, Vec<String>) {
+        match self {
+            // -singlefileでは出力ファイル名に拡張子が付く
+            Rasterizer::Pdftoppm => (
+                "pdftoppm",
+                vec![
+                    "-png".to_string(),
+                    "-singlefile".to_string(),
+                    "-r".to_string(),
+                    dpi.to_string(),
+                    "-f".to_string(),
+                    page.number.to_string(),
+                    "-l".to_string(),
+                    page.number.to_string(),
+                    page.path.clone(),
+                    output.trim_end_matches(".png").to_string(),
+                ],
+            ),
+            Rasterizer::Mutool => (
+                "mutool",
+                vec![
+                    "draw".to_string(),
+                    "-r".to_string(),
+                    dpi.to_string(),
+                    "-o".to_string(),
+                    output.to_string(),
+                    page.path.clone(),
+                    page.number.to_string(),
+                ],
+            ),
+        }
+    }
+}
+
+// 解像度(DPI)。環境変数DEFAULT_PDF_DPI、既定は150
+pub fn dpi_from_env() -> Result<u32, String> {
+    match std::env::var("DEFAULT_PDF_DPI") {
+        Ok(dpi) if !dpi.trim().is_empty() => dpi
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| format!("invalid pdf dpi: {}", e)),
+        _ => Ok(150),
+    }
+}
+
+// pdfinfo、mutool infoの出力からページ数を取得する
+// 例) Pages:          12
+fn parse_page_count(output: &str) -> Option<u32> {
+    output.lines().find_map(|line| {
+        let count = line.trim().strip_prefix("Pages:")?;
+        count.trim().parse::<u32>().ok()
+    })
+}
+
+// PDFのページ数
+pub fn page_count(path: &str) -> Result<u32, String> {
+    let (command, args) = Rasterizer::from_env()?.info_args(path);
+    let result = std::process::Command::new(command)
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", command, e))?;
+    if !result.status.success() {
+        return Err(format!("failed to read pdf info: {:?}", result));
+    }
+    parse_page_count(&String::from_utf8_lossy(&result.stdout))
+        .filter(|count| *count > 0)
+        .ok_or(format!("page count not found: {}", path))
+}
+
+// PDFの内容のダイジェスト(SHA-256)
+fn file_digest(path: &str) -> Result<String, String> {
+    let mut file =
+        std::fs::File::open(path).map_err(|e| format!("failed to read pdf {}: {}", path, e))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .map_err(|e| format!("failed to read pdf {}: {}", path, e))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// キャッシュのキー
+// PDFの内容、ページ、解像度、変換コマンドが同じであれば同じ画像を使う
+fn cache_key(digest: &str, page: u32, dpi: u32, rasterizer: Rasterizer) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}:{}:{}:{:?}", digest, page, dpi, rasterizer));
+    format!("{:x}", hasher.finalize())[..32].to_string()
+}
+
+// PDFのページを画像に変換する
+// PDFの内容のダイジェストはファイルごとに一度だけ求める
+#[derive(Debug, Default)]
+pub struct Resolver {
+    digests: HashMap<String, String>,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    fn digest(&mut self, path: &str) -> Result<String, String> {
+        if let Some(digest) = self.digests.get(path) {
+            return Ok(digest.clone());
+        }
+        let digest = file_digest(path)?;
+        self.digests.insert(path.to_string(), digest.clone());
+        Ok(digest)
+    }
+
+    // スライドのファイル名を解決する
+    // PDFのページ指定の場合は画像に変換し、そのパスを返す。それ以外はそのまま返す
+    pub fn resolve(&mut self, filename: &str) -> Result<String, String> {
+        let Some(page) = Page::parse(filename)? else {
+            return Ok(filename.to_string());
+        };
+
+        let rasterizer = Rasterizer::from_env()?;
+        let dpi = dpi_from_env()?;
+        let digest = self.digest(&page.path)?;
+
+        let output_dir = utils::target_path_from_env("DEFAULT_OUTPUT_SLIDE_FILE_DIR");
+        std::fs::create_dir_all(&output_dir).map_err(|e| e.to_string())?;
+        let output = output_dir
+            .join(format!(
+                "pdf-{}.png",
+                cache_key(&digest, page.number, dpi, rasterizer)
+            ))
+            .to_string_lossy()
+            .into_owned();
+        if std::path::Path::new(&output).exists() {
+            return Ok(output);
+        }
+
+        rasterize(&page, dpi, rasterizer, &output)?;
+        info!("pdf page rasterized: {} -> {}", filename, output);
+
+        Ok(output)
+    }
+}
+
+fn rasterize(page: &Page, dpi: u32, rasterizer: Rasterizer, output: &str) -> Result<(), String> {
+    let (command, args) = rasterizer.args(page, dpi, output);
+    let result = std::process::Command::new(command)
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", command, e))?;
+    if !result.status.success() {
+        return Err(format!("failed rasterize pdf: {:?}", result));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_args() {
+        assert_eq!(Page::parse("slide.png").unwrap(), None);
+        assert!(Page::parse("deck.pdf#0").is_err());
+        assert_eq!(
+            Page::parse("deck.PDF").unwrap(),
+            Some(Page {
+                path: "deck.PDF".to_string(),
+                number: 1,
+            })
+        );
+
+        let page = Page::parse("C:\\slides\\deck.pdf#3").unwrap().unwrap();
+        assert_eq!(page.number, 3);
+        let (command, args) = Rasterizer::Pdftoppm.args(&page, 150, "out/pdf-1.png");
+        assert_eq!(command, "pdftoppm");
+        assert_eq!(
+            args,
+            vec![
+                "-png",
+                "-singlefile",
+                "-r",
+                "150",
+                "-f",
+                "3",
+                "-l",
+                "3",
+                "C:\\slides\\deck.pdf",
+                "out/pdf-1"
+            ]
+        );
+
+        assert_ne!(
+            cache_key("digest", 1, 150, Rasterizer::Pdftoppm),
+            cache_key("digest", 2, 150, Rasterizer::Pdftoppm)
+        );
+        // 実行ごとに同じキーになる
+        assert_eq!(
+            cache_key("digest", 1, 150, Rasterizer::Pdftoppm),
+            "f71af22af6be5bfb999986f24c1aa0f2"
+        );
+
+        assert_eq!(all_pages("deck.pdf#*"), Some("deck.pdf"));
+        assert_eq!(all_pages("slides/*.png"), None);
+        assert_eq!(
+            parse_page_count("Title:          deck\nPages:          12\n"),
+            Some(12)
+        );
+    }
+}
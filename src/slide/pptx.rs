@@ -0,0 +1,389 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+use crate::slide::{
+    section::{Content, Section},
+    utils, video_option,
+};
+
+// PowerPoint(.pptx)のノートからセクションを生成する
+// スライドごとのノートの段落がテキストになる
+// スライドの画像は、書き出した画像のディレクトリ(DEFAULT_PPTX_IMAGE_DIR)を使うか、
+// LibreOfficeでPDFに変換してページを画像にする
+
+const RELATIONSHIPS_NS: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+// スライドのタイトルとノート
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlideNotes {
+    pub title: Option<String>,
+    // ノートの段落
+    pub notes: Vec<String>,
+}
+
+pub fn create_vec(pptx_path: &Path, theme: video_option::Op) -> Result<Vec<Section>, String> {
+    let slides = read_notes(pptx_path)?;
+    let images = slide_images(pptx_path, slides.len())?;
+
+    let mut sections = vec![];
+    for (slide, image) in slides.into_iter().zip(images) {
+        // ノートのないスライドは読み上げるものがないため飛ばす
+        if slide.notes.is_empty() {
+            warn!("slide without notes is skipped: {}", image);
+            continue;
+        }
+
+        let mut section = Section::blanc();
+        section.filename = image;
+        section.title = slide.title;
+        section.style = theme.clone();
+        for paragraph in slide.notes {
+            // @ボイスID テキスト の形式の場合は話者を指定する
+            let content = match paragraph
+                .strip_prefix('@')
+                .and_then(|rest| rest.split_once(' '))
+            {
+                Some((voice_id, text)) if voice_id.parse::<i32>().is_ok() => {
+                    Content::new(voice_id.parse::<i32>().ok(), text.trim().to_string())
+                }
+                _ => Content::new(None, paragraph),
+            };
+            section.contents.push(content);
+        }
+        sections.push(section);
+    }
+
+    Ok(sections)
+}
+
+// スライドの順にタイトルとノートを読み込む
+// 非表示のスライドは書き出されないため除く
+pub fn read_notes(pptx_path: &Path) -> Result<Vec<SlideNotes>, String> {
+    let file = std::fs::File::open(pptx_path)
+        .map_err(|e| format!("failed to open pptx {}: {}", pptx_path.display(), e))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+
+    let presentation = read_part(&mut archive, "ppt/presentation.xml")?;
+    let presentation_rels = read_part(&mut archive, "ppt/_rels/presentation.xml.rels")?;
+    let slide_parts = {
+        let document = parse_xml(&presentation)?;
+        let rels = relationships(&presentation_rels, "ppt")?;
+        document
+            .descendants()
+            .filter(|node| node.tag_name().name() == "sldId")
+            .filter_map(|node| node.attribute((RELATIONSHIPS_NS, "id")))
+            .filter_map(|id| rels.iter().find(|(rel_id, _)| rel_id == id))
+            .map(|(_, target)| target.clone())
+            .collect::<Vec<String>>()
+    };
+
+    let mut slides = vec![];
+    for slide_part in slide_parts {
+        let slide = read_part(&mut archive, &slide_part)?;
+        let slide_document = parse_xml(&slide)?;
+        if slide_document.root_element().attribute("show") == Some("0") {
+            continue;
+        }
+        let title = placeholder_paragraphs(&slide_document, &["title", "ctrTitle"])
+            .into_iter()
+            .next();
+
+        // スライドのリレーションからノートを探す
+        let (dir, name) = slide_part.rsplit_once('/').unwrap_or(("", &slide_part));
+        let rels_part = format!("{}/_rels/{}.rels", dir, name);
+        let notes = match read_part(&mut archive, &rels_part) {
+            Ok(rels) => {
+                let notes_part = relationships(&rels, dir)?
+                    .into_iter()
+                    .find(|(_, target)| target.contains("notesSlide"))
+                    .map(|(_, target)| target);
+                match notes_part {
+                    Some(notes_part) => {
+                        let notes = read_part(&mut archive, &notes_part)?;
+                        placeholder_paragraphs(&parse_xml(&notes)?, &["body"])
+                    }
+                    None => vec![],
+                }
+            }
+            Err(_) => vec![],
+        };
+
+        slides.push(SlideNotes { title, notes });
+    }
+
+    Ok(slides)
+}
+
+fn read_part(archive: &mut zip::ZipArchive<std::fs::File>, name: &str) -> Result<String, String> {
+    let mut part = archive
+        .by_name(name)
+        .map_err(|e| format!("failed to read {}: {}", name, e))?;
+    let mut text = String::new();
+    part.read_to_string(&mut text).map_err(|e| e.to_string())?;
+    Ok(text)
+}
+
+fn parse_xml(text: &str) -> Result<roxmltree::Document<'_>, String> {
+    roxmltree::Document::parse(text).map_err(|e| e.to_string())
+}
+
+// リレーション(Id, パッケージ内のパス)の一覧
+// base_dir: リレーションの元のパートのディレクトリ
+fn relationships(text: &str, base_dir: &str) -> Result<Vec<(String, String)>, String> {
+    let document = parse_xml(text)?;
+    Ok(document
+        .descendants()
+        .filter(|node| node.tag_name().name() == "Relationship")
+        .filter(|node| node.attribute("TargetMode") != Some("External"))
+        .filter_map(|node| {
+            let id = node.attribute("Id")?;
+            let target = node.attribute("Target")?;
+            Some((id.to_string(), resolve_part(base_dir, target)))
+        })
+        .collect())
+}
+
+// 相対パス(../notesSlides/notesSlide1.xml など)をパッケージ内のパスにする
+fn resolve_part(base_dir: &str, target: &str) -> String {
+    if let Some(absolute) = target.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut parts = base_dir
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>();
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+// 指定した種類のプレースホルダーの段落(空の段落を除く)
+fn placeholder_paragraphs(document: &roxmltree::Document, types: &[&str]) -> Vec<String> {
+    document
+        .descendants()
+        .filter(|node| node.tag_name().name() == "sp")
+        .filter(|shape| {
+            shape
+                .descendants()
+                .find(|node| node.tag_name().name() == "ph")
+                .and_then(|ph| ph.attribute("type"))
+                .is_some_and(|kind| types.contains(&kind))
+        })
+        .flat_map(|shape| {
+            shape
+                .descendants()
+                .filter(|node| node.tag_name().name() == "p")
+                .map(|paragraph| {
+                    paragraph
+                        .descendants()
+                        .filter(|node| node.tag_name().name() == "t")
+                        .filter_map(|node| node.text())
+                        .collect::<String>()
+                })
+                .collect::<Vec<String>>()
+        })
+        .map(|paragraph| paragraph.trim().to_string())
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect()
+}
+
+// スライドの画像(スライドの順)
+// DEFAULT_PPTX_IMAGE_DIRがある場合は、ファイル名の番号順の画像を使う(例: スライド1.PNG)
+// ない場合はLibreOfficeでPDFに変換し、ページ指定(deck.pdf#1)を返す
+fn slide_images(pptx_path: &Path, count: usize) -> Result<Vec<String>, String> {
+    match std::env::var("DEFAULT_PPTX_IMAGE_DIR") {
+        Ok(dir) if !dir.is_empty() => images_from_dir(
+            &utils::target_path_from_env("DEFAULT_PPTX_IMAGE_DIR"),
+            count,
+        ),
+        _ => {
+            let pdf = convert_to_pdf(pptx_path)?;
+            Ok((1..=count)
+                .map(|page| format!("{}#{}", pdf, page))
+                .collect())
+        }
+    }
+}
+
+fn images_from_dir(dir: &Path, count: usize) -> Result<Vec<String>, String> {
+    let mut images = std::fs::read_dir(dir)
+        .map_err(|e| format!("failed to read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| {
+                ["png", "jpg", "jpeg"].contains(&ext.to_string_lossy().to_lowercase().as_str())
+            })
+        })
+        .filter_map(|path| Some((slide_number(&path)?, path)))
+        .collect::<Vec<(u32, PathBuf)>>();
+    images.sort_by_key(|(number, _)| *number);
+
+    if images.len() < count {
+        return Err(format!(
+            "slide images are not enough: {} images for {} slides in {}",
+            images.len(),
+            count,
+            dir.display()
+        ));
+    }
+
+    Ok(images
+        .into_iter()
+        .take(count)
+        .map(|(_, path)| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+// ファイル名の最後の数字
+fn slide_number(path: &Path) -> Option<u32> {
+    let stem = path.file_stem()?.to_string_lossy().into_owned();
+    let digits = stem
+        .chars()
+        .rev()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>();
+    digits.chars().rev().collect::<String>().parse().ok()
+}
+
+// LibreOffice(headless)でPDFに変換する
+// 出力先はDEFAULT_OUTPUT_SLIDE_FILE_DIR
+fn convert_to_pdf(pptx_path: &Path) -> Result<String, String> {
+    let output_dir = utils::target_path_from_env("DEFAULT_OUTPUT_SLIDE_FILE_DIR");
+    std::fs::create_dir_all(&output_dir).map_err(|e| e.to_string())?;
+    let command = std::env::var("DEFAULT_LIBREOFFICE_PATH")
+        .ok()
+        .filter(|command| !command.is_empty())
+        .unwrap_or("soffice".to_string());
+
+    let output = std::process::Command::new(&command)
+        .args([
+            "--headless".to_string(),
+            "--convert-to".to_string(),
+            "pdf".to_string(),
+            "--outdir".to_string(),
+            output_dir.to_string_lossy().into_owned(),
+            pptx_path.to_string_lossy().into_owned(),
+        ])
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", command, e))?;
+    if !output.status.success() {
+        return Err(format!("failed convert pptx to pdf: {:?}", output));
+    }
+
+    let pdf = output_dir
+        .join(
+            pptx_path
+                .with_extension("pdf")
+                .file_name()
+                .unwrap_or_default(),
+        )
+        .to_string_lossy()
+        .into_owned();
+    info!("pptx converted: {}", pdf);
+
+    Ok(pdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn notes_xml(paragraphs: &[&str]) -> String {
+        let paragraphs = paragraphs
+            .iter()
+            .map(|text| format!("<a:p><a:r><a:t>{}</a:t></a:r></a:p>", text))
+            .collect::<String>();
+        format!(
+            r#"<p:notes xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main" xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main"><p:cSld><p:spTree>
+            <p:sp><p:nvSpPr><p:nvPr><p:ph type="sldImg"/></p:nvPr></p:nvSpPr></p:sp>
+            <p:sp><p:nvSpPr><p:nvPr><p:ph type="body" idx="1"/></p:nvPr></p:nvSpPr><p:txBody>{}</p:txBody></p:sp>
+            </p:spTree></p:cSld></p:notes>"#,
+            paragraphs
+        )
+    }
+
+    #[test]
+    fn test_read_notes() {
+        let path = std::env::temp_dir().join(format!("{}.pptx", uuid::Uuid::new_v4()));
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        let parts = [
+            (
+                "ppt/presentation.xml",
+                r#"<p:presentation xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><p:sldIdLst><p:sldId id="257" r:id="rId3"/><p:sldId id="256" r:id="rId2"/><p:sldId id="258" r:id="rId4"/></p:sldIdLst></p:presentation>"#.to_string(),
+            ),
+            (
+                "ppt/_rels/presentation.xml.rels",
+                r#"<Relationships><Relationship Id="rId2" Target="slides/slide1.xml"/><Relationship Id="rId3" Target="slides/slide2.xml"/><Relationship Id="rId4" Target="slides/slide3.xml"/></Relationships>"#.to_string(),
+            ),
+            (
+                "ppt/slides/slide1.xml",
+                r#"<p:sld xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main" xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main"><p:cSld><p:spTree><p:sp><p:nvSpPr><p:nvPr><p:ph type="title"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>はじめに</a:t></a:r></a:p></p:txBody></p:sp></p:spTree></p:cSld></p:sld>"#.to_string(),
+            ),
+            (
+                "ppt/slides/slide2.xml",
+                r#"<p:sld xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main"/>"#.to_string(),
+            ),
+            (
+                "ppt/slides/slide3.xml",
+                r#"<p:sld show="0" xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main"/>"#.to_string(),
+            ),
+            (
+                "ppt/slides/_rels/slide1.xml.rels",
+                r#"<Relationships><Relationship Id="rId2" Target="../notesSlides/notesSlide1.xml"/></Relationships>"#.to_string(),
+            ),
+            (
+                "ppt/notesSlides/notesSlide1.xml",
+                notes_xml(&["これは説明です。", "", "@3 別の話者です。"]),
+            ),
+        ];
+        for (name, text) in parts {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(text.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let slides = read_notes(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // プレゼンテーションの順(slide2, slide1)で、非表示のslide3は除く
+        assert_eq!(
+            slides,
+            vec![
+                SlideNotes {
+                    title: None,
+                    notes: vec![],
+                },
+                SlideNotes {
+                    title: Some("はじめに".to_string()),
+                    notes: vec![
+                        "これは説明です。".to_string(),
+                        "@3 別の話者です。".to_string()
+                    ],
+                },
+            ]
+        );
+
+        assert_eq!(slide_number(Path::new("out/スライド12.PNG")), Some(12));
+        assert_eq!(
+            resolve_part("ppt/slides", "../notesSlides/notesSlide1.xml"),
+            "ppt/notesSlides/notesSlide1.xml"
+        );
+    }
+}
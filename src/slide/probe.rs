@@ -0,0 +1,142 @@
+// ffprobeによる動画ファイルのパラメータ取得
+
+// 連結時に一致している必要があるストリームのパラメータ
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamParams {
+    pub video_codec: Option<String>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub pix_fmt: Option<String>,
+    pub time_base: Option<String>,
+    pub frame_rate: Option<String>,
+    pub audio_codec: Option<String>,
+    pub sample_rate: Option<String>,
+    pub channels: Option<i64>,
+    pub channel_layout: Option<String>,
+}
+
+impl StreamParams {
+    // ffprobe -of json の出力を解析する
+    pub fn parse(json: &str) -> Result<StreamParams, String> {
+        let json: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let streams = json
+            .get("streams")
+            .and_then(|streams| streams.as_array())
+            .ok_or("streams not found".to_string())?;
+
+        let string = |stream: &serde_json::Value, key: &str| {
+            stream
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string())
+        };
+        let int = |stream: &serde_json::Value, key: &str| stream.get(key).and_then(|v| v.as_i64());
+
+        let mut params = StreamParams::default();
+        // 最初の映像・音声ストリームを対象とする
+        if let Some(video) = streams
+            .iter()
+            .find(|s| s.get("codec_type").and_then(|v| v.as_str()) == Some("video"))
+        {
+            params.video_codec = string(video, "codec_name");
+            params.width = int(video, "width");
+            params.height = int(video, "height");
+            params.pix_fmt = string(video, "pix_fmt");
+            params.time_base = string(video, "time_base");
+            params.frame_rate = string(video, "r_frame_rate");
+        }
+        if let Some(audio) = streams
+            .iter()
+            .find(|s| s.get("codec_type").and_then(|v| v.as_str()) == Some("audio"))
+        {
+            params.audio_codec = string(audio, "codec_name");
+            params.sample_rate = string(audio, "sample_rate");
+            params.channels = int(audio, "channels");
+            params.channel_layout = string(audio, "channel_layout");
+        }
+
+        Ok(params)
+    }
+
+    // 一致しないパラメータ名の一覧
+    pub fn differences(&self, other: &StreamParams) -> Vec<&'static str> {
+        let mut differences = vec![];
+        if self.video_codec != other.video_codec {
+            differences.push("video_codec");
+        }
+        if self.width != other.width || self.height != other.height {
+            differences.push("resolution");
+        }
+        if self.pix_fmt != other.pix_fmt {
+            differences.push("pix_fmt");
+        }
+        if self.time_base != other.time_base {
+            differences.push("time_base");
+        }
+        if self.frame_rate != other.frame_rate {
+            differences.push("frame_rate");
+        }
+        if self.audio_codec != other.audio_codec {
+            differences.push("audio_codec");
+        }
+        if self.sample_rate != other.sample_rate {
+            differences.push("sample_rate");
+        }
+        if self.channels != other.channels || self.channel_layout != other.channel_layout {
+            differences.push("channel_layout");
+        }
+        differences
+    }
+}
+
+pub async fn stream_params(filepath: &str) -> Result<StreamParams, String> {
+    let output = std::process::Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "stream=codec_type,codec_name,width,height,pix_fmt,time_base,r_frame_rate,sample_rate,channels,channel_layout",
+            "-of",
+            "json",
+            filepath,
+        ])
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(format!("failed probe: {:?}", output));
+    }
+
+    StreamParams::parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_differences() {
+        let json = r#"{
+            "streams": [
+                {"codec_name": "hevc", "codec_type": "video", "width": 1920, "height": 1080,
+                 "pix_fmt": "yuv420p", "r_frame_rate": "25/1", "time_base": "1/12800"},
+                {"codec_name": "aac", "codec_type": "audio", "sample_rate": "24000",
+                 "channels": 1, "channel_layout": "mono", "r_frame_rate": "0/0", "time_base": "1/24000"}
+            ]
+        }"#;
+
+        let params = StreamParams::parse(json).unwrap();
+        assert_eq!(params.video_codec.as_deref(), Some("hevc"));
+        assert_eq!(params.width, Some(1920));
+        assert_eq!(params.sample_rate.as_deref(), Some("24000"));
+        assert!(params.differences(&params).is_empty());
+
+        let mut other = params.clone();
+        other.height = Some(1920);
+        other.sample_rate = Some("48000".to_string());
+        assert_eq!(
+            params.differences(&other),
+            vec!["resolution", "sample_rate"]
+        );
+    }
+}
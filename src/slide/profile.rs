@@ -0,0 +1,107 @@
+use std::path::Path;
+
+use crate::slide::video_option::{Fill, Op};
+
+// 出力プロファイル
+// 解像度と、それに合わせた字幕の大きさ、セーフエリアの余白をまとめたもの
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    // 出力ファイル名に付ける名前
+    pub name: String,
+    pub w: i16,
+    pub h: i16,
+    pub font_size: i32,
+    pub title_font_size: i32,
+    pub margin_x: i32,
+    pub margin_y: i32,
+    // 余白の埋め方
+    pub fill: Fill,
+}
+
+impl Profile {
+    // プロファイル名から作る
+    // landscape(16:9 1080p)、portrait(9:16)、square(1:1)、4k(16:9 2160p)
+    pub fn parse(value: &str) -> Result<Profile, String> {
+        let profile = |name: &str, w, h, font_size, title_font_size, margin_x, margin_y| Profile {
+            name: name.to_string(),
+            w,
+            h,
+            font_size,
+            title_font_size,
+            margin_x,
+            margin_y,
+            fill: Fill::Color,
+        };
+
+        match value.trim().to_lowercase().as_str() {
+            "landscape" | "1080p" | "16:9" => Ok(profile("landscape", 1920, 1080, 36, 64, 96, 50)),
+            // ショート動画のUI(下部の説明文、右側のボタン)に字幕が隠れないよう余白を広くとる
+            "portrait" | "vertical" | "9:16" => {
+                // 横長のスライドは上下の余白が大きくなるため、ぼかした背景で埋める
+                Ok(Profile {
+                    fill: Fill::Blur,
+                    ..profile("portrait", 1080, 1920, 56, 80, 120, 400)
+                })
+            }
+            "square" | "1:1" => Ok(profile("square", 1080, 1080, 40, 64, 64, 80)),
+            "4k" | "2160p" => Ok(profile("4k", 3840, 2160, 72, 128, 192, 100)),
+            other => Err(format!("unknown output profile: {}", other)),
+        }
+    }
+
+    // 環境変数DEFAULT_OUTPUT_PROFILESのプロファイル(カンマ区切り)
+    // 未指定の場合は空で、テーマの解像度で一つだけ出力する
+    pub fn from_env() -> Result<Vec<Profile>, String> {
+        std::env::var("DEFAULT_OUTPUT_PROFILES")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|value| !value.trim().is_empty())
+            .map(Profile::parse)
+            .collect()
+    }
+
+    // 字幕の大きさと余白の設定
+    // テーマやヘッダーで指定した場合はそちらを優先する
+    pub fn to_op(&self) -> Op {
+        let mut op = Op::empty();
+        op.font_size = Some(self.font_size.to_string());
+        op.title_font_size = Some(self.title_font_size.to_string());
+        op.margin_x = Some(self.margin_x);
+        op.margin_y = Some(self.margin_y);
+        op.fill = Some(self.fill);
+        op
+    }
+
+    // 出力ファイル名にプロファイル名を付ける
+    // 例) concat-xxx.mp4 -> concat-xxx-portrait.mp4
+    pub fn output_filename(&self, filepath: &str) -> String {
+        let path = Path::new(filepath);
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let filename = match path.extension() {
+            Some(extension) => format!("{}-{}.{}", stem, self.name, extension.to_string_lossy()),
+            None => format!("{}-{}", stem, self.name),
+        };
+        path.with_file_name(filename).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let profile = Profile::parse("9:16").unwrap();
+        assert_eq!(profile.name, "portrait");
+        assert_eq!((profile.w, profile.h), (1080, 1920));
+        assert!(Profile::parse("8k").is_err());
+
+        assert_eq!(
+            profile.output_filename("/tmp/concat-1.mp4"),
+            "/tmp/concat-1-portrait.mp4"
+        );
+    }
+}
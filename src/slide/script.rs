@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::slide::{
+    section::{Content, Section},
+    transition, utils, video_option, voicevox,
+};
+
+// YAML/JSON形式のスクリプト
+// スキーマは resource/schema/script.schema.json
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    // ドキュメント全体に適用するテーマファイル
+    pub theme: Option<String>,
+    // ドキュメント全体のスタイル(テーマに上書きされる)
+    pub style: Option<video_option::Op>,
+    pub sections: Vec<ScriptSection>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptSection {
+    // スライドとして使用する画像または動画ファイル
+    pub file: String,
+    pub title: Option<String>,
+    // 前のスライドからのトランジション(例: fade:0.5)
+    pub transition: Option<String>,
+    // このスライドに適用するテーマファイル
+    pub theme: Option<String>,
+    // このスライドのスタイル
+    pub style: Option<video_option::Op>,
+    #[serde(default)]
+    pub lines: Vec<ScriptLine>,
+}
+
+// テキストのみ、または話者や読み上げ方を指定した行
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ScriptLine {
+    Text(String),
+    Line {
+        text: String,
+        // VOICEVOXのボイスID
+        speaker: Option<i32>,
+        prosody: Option<voicevox::Prosody>,
+        // 読み上げ後の無音(秒)。prosody.pauseと同じ
+        pause: Option<f64>,
+    },
+}
+
+impl ScriptLine {
+    fn to_content(&self) -> Content {
+        match self {
+            ScriptLine::Text(text) => Content::new(None, text.clone()),
+            ScriptLine::Line {
+                text,
+                speaker,
+                prosody,
+                pause,
+            } => {
+                let mut content = Content::new(*speaker, text.clone());
+                content.prosody = prosody.clone().unwrap_or_default();
+                if pause.is_some() {
+                    content.prosody.pause = *pause;
+                }
+                content
+            }
+        }
+    }
+}
+
+impl Script {
+    // 拡張子が.jsonの場合はJSON、それ以外はYAMLとして扱う
+    pub fn parse(text: &str, is_json: bool) -> Result<Script, String> {
+        if is_json {
+            serde_json::from_str(text).map_err(|e| format!("failed to parse script: {}", e))
+        } else {
+            serde_norway::from_str(text).map_err(|e| format!("failed to parse script: {}", e))
+        }
+    }
+
+    // セクションに変換する
+    // theme: 環境変数で指定したドキュメント全体のテーマ
+    // base_dir: テーマの相対パスの基準となるディレクトリ
+    pub fn into_sections(
+        self,
+        mut theme: video_option::Op,
+        base_dir: &Path,
+    ) -> Result<Vec<Section>, String> {
+        if let Some(path) = self.theme.as_deref() {
+            let path = utils::absolute_path(Path::new(path), base_dir);
+            theme.merge(&video_option::Op::from_file(&path)?);
+        }
+        if let Some(style) = self.style.as_ref() {
+            theme.merge(style);
+        }
+
+        let mut sections = vec![];
+        for script_section in self.sections {
+            let mut section = Section::blanc();
+            section.filename = script_section.file;
+            section.title = script_section.title;
+            section.style = theme.clone();
+            if let Some(value) = script_section.transition.as_deref() {
+                section.transition = transition::Transition::parse(value)?;
+            }
+            if let Some(path) = script_section.theme.as_deref() {
+                let path = utils::absolute_path(Path::new(path), base_dir);
+                section.style.merge(&video_option::Op::from_file(&path)?);
+            }
+            if let Some(style) = script_section.style.as_ref() {
+                section.style.merge(style);
+            }
+            section.contents = script_section
+                .lines
+                .iter()
+                .map(|line| line.to_content())
+                .collect();
+            sections.push(section);
+        }
+
+        Ok(sections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let project_dir = env!("CARGO_MANIFEST_DIR");
+        let text =
+            std::fs::read_to_string(Path::new(project_dir).join("resource/script.sample.yaml"))
+                .unwrap();
+        let sections = Script::parse(&text, false)
+            .unwrap()
+            .into_sections(video_option::Op::empty(), Path::new("."))
+            .unwrap();
+
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].title.as_deref(), Some("はじめに"));
+        assert_eq!(sections[0].style.font_size.as_deref(), Some("40"));
+        assert_eq!(sections[0].style.font_color.as_deref(), Some("black"));
+        assert_eq!(sections[0].contents[0].voice_id, None);
+        let line = &sections[0].contents[1];
+        assert_eq!(line.voice_id, Some(3));
+        assert_eq!(line.prosody.speed, Some(1.2));
+        assert_eq!(line.prosody.pause, Some(0.5));
+        assert!(sections[1].transition.is_some());
+
+        let json = r#"{"sections": [{"file": "a.png", "lines": ["テキスト"]}]}"#;
+        let sections = Script::parse(json, true)
+            .unwrap()
+            .into_sections(video_option::Op::empty(), Path::new("."))
+            .unwrap();
+        assert_eq!(sections[0].contents[0].text, "テキスト");
+
+        // 未知の項目はエラー
+        let json = r#"{"sections": [{"file": "a.png", "line": []}]}"#;
+        assert!(Script::parse(json, true).is_err());
+        let yaml = "sections:\n  - file: a.png\n    style:\n      font_colour: red\n";
+        assert!(Script::parse(yaml, false).is_err());
+
+        // スキーマはJSONとして読み込める
+        let schema = std::fs::read_to_string(
+            Path::new(project_dir).join("resource/schema/script.schema.json"),
+        )
+        .unwrap();
+        assert!(serde_json::from_str::<serde_json::Value>(&schema).is_ok());
+    }
+}
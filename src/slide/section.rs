@@ -1,11 +1,18 @@
-use std::{collections::HashMap, path::PathBuf, vec};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    vec,
+};
 
 use log::{info, warn};
 
-use crate::slide::{utils, video, video_option, voice};
+use crate::slide::{
+    character, job, loudness, markdown, pdf, pptx, profile, script, speaker, subtitle, transition,
+    utils, video, video_option, voice, voicevox,
+};
 
 /* 例）
-[適用ファイル名（絶対パス名OK、suffix照合）]
+[適用ファイル名（絶対パス名OK、suffix照合） | 属性=値, 属性=値]
 # タイトル
 本文(行区切り)
 本文(行区切り)
@@ -25,6 +32,14 @@ pub struct Section {
     pub filename: String,
     pub title: Option<String>,
     pub contents: Vec<Content>,
+    // このセクションに入る際のトランジション
+    // Noneの場合は全体設定(DEFAULT_TRANSITION)に従う
+    pub transition: Option<transition::Transition>,
+    // このセクションの動画生成オプション
+    // テーマとヘッダーの属性で指定された項目のみを持ち、デフォルト値に上書きする
+    pub style: video_option::Op,
+    // ヘッダーを記述したファイルと行番号(エラー表示用)
+    pub source: Option<String>,
 
     // 以下は、音声化のための情報
     // voices keyはcontenst keyと対になり、数が一致する
@@ -32,6 +47,8 @@ pub struct Section {
 
     // 以下は、動画化のための情報
     pub video: Option<String>,
+    // テキストごとの動画(contents keyと対になる)。テキストごとに生成した場合のみ
+    pub parts: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +56,8 @@ pub struct Content {
     pub key: String,
     pub voice_id: Option<i32>,
     pub text: String,
+    // 読み上げ方の調整(話速、音高、読み上げ後の無音など)
+    pub prosody: voicevox::Prosody,
 }
 
 impl Content {
@@ -48,7 +67,75 @@ impl Content {
             key,
             voice_id,
             text,
+            prosody: voicevox::Prosody::default(),
+        }
+    }
+}
+
+// スクリプトの行と、その行を記述したファイル、行番号
+#[derive(Debug, Clone)]
+struct SourceLine {
+    path: PathBuf,
+    // 1から始まる行番号
+    number: usize,
+    text: String,
+}
+
+impl SourceLine {
+    // テキストを行に分割し、!include ファイルパス の行をそのファイルの行に置き換える
+    // 相対パスは記述したファイルのディレクトリを基準にする
+    // stack: 展開中のファイル(循環の検出用)
+    // エラーは原因となった行の位置を一度だけ付けて返す
+    fn expand(
+        path: &Path,
+        text: &str,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<Vec<SourceLine>, String> {
+        stack.push(path.to_path_buf());
+
+        let mut lines = vec![];
+        // Platform-independent line splitting (handles both \r\n and \n)
+        for (index, text) in text.lines().enumerate() {
+            let line = SourceLine {
+                path: path.to_path_buf(),
+                number: index + 1,
+                text: text.to_string(),
+            };
+            let Some(include) = line.text.trim().strip_prefix("!include ") else {
+                lines.push(line);
+                continue;
+            };
+
+            let include = utils::absolute_path(Path::new(include.trim()), line.dir());
+            if stack.contains(&include) {
+                let chain = stack
+                    .iter()
+                    .chain(std::iter::once(&include))
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(" -> ");
+                return Err(line.error(format!("include cycle: {}", chain)));
+            }
+            let text = std::fs::read_to_string(&include)
+                .map_err(|e| line.error(format!("failed to read {}: {}", include.display(), e)))?;
+            let text = text.trim_start_matches("\u{feff}");
+            lines.extend(SourceLine::expand(&include, text, stack)?);
         }
+
+        stack.pop();
+        Ok(lines)
+    }
+
+    fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    fn location(&self) -> String {
+        format!("{}:{}", self.path.display(), self.number)
+    }
+
+    fn error(&self, e: String) -> String {
+        format!("{}: {}", self.location(), e)
     }
 }
 
@@ -58,13 +145,168 @@ impl Section {
             filename: "".to_string(),
             title: None,
             contents: vec![],
+            transition: None,
+            style: video_option::Op::empty(),
+            source: None,
             voices: HashMap::new(),
             video: None,
+            parts: HashMap::new(),
         }
     }
 
+    // スクリプトを読み込み、セクションを生成する
+    // 相対パスのスライドはスクリプトのディレクトリを基準にする
+    // ワイルドカードのスライドは一致したファイルごとのセクションに展開する
+    // PDFのすべてのページ(deck.pdf#*)を指定したスライドはページごとのセクションに展開する
+    // PDFのページを指定したスライドは画像に変換する
     pub fn create_vec(resource_text: PathBuf) -> Result<Vec<Section>, String> {
-        let mut f = std::fs::File::open(resource_text).map_err(|e| e.to_string())?;
+        let current_dir = std::env::current_dir().map_err(|e| e.to_string())?;
+        let base_dir = utils::absolute_path(&resource_text, &current_dir)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or(current_dir);
+
+        let mut sections = vec![];
+        for mut section in Section::parse_vec(resource_text)? {
+            section.filename = Section::resolve_path(&section.filename, &base_dir);
+            let source = section.source.clone();
+            let expanded = if let Some(path) = pdf::all_pages(&section.filename) {
+                let path = path.to_string();
+                pdf::page_count(&path).and_then(|count| {
+                    let pages = (1..=count)
+                        .map(|number| format!("{}#{}", path, number))
+                        .collect();
+                    section.expand(pages)
+                })
+            } else if utils::is_glob(&section.filename) {
+                utils::expand_glob(Path::new(&section.filename)).and_then(|paths| {
+                    let paths = paths
+                        .into_iter()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .collect();
+                    section.expand(paths)
+                })
+            } else {
+                Ok(vec![section])
+            };
+            sections.extend(expanded.map_err(|e| Section::with_source(source.as_deref(), e))?);
+        }
+        // 同じPDFの内容は一度だけ読み込む
+        let mut pdf_resolver = pdf::Resolver::new();
+        for section in sections.iter_mut() {
+            section.filename = pdf_resolver
+                .resolve(&section.filename)
+                .map_err(|e| Section::with_source(section.source.as_deref(), e))?;
+        }
+        Section::assign_keys(&mut sections);
+
+        Ok(sections)
+    }
+
+    // テキストの内容(ボイスID、テキスト、読み上げ方)と音声の生成設定から決まるkeyを付ける
+    // 再実行時に完了した音声と動画を再利用するため、実行ごとに同じkeyにする
+    // エンジンやラウドネス正規化の設定を変えた場合は音声を生成し直す
+    // 同じ内容のテキストは出現順の番号で区別する
+    fn assign_keys(sections: &mut [Section]) {
+        let default_voice_id = std::env::var("DEFAULT_VOICEVOX_VOICE_ID").unwrap_or_default();
+        let server_url = std::env::var("DEFAULT_VOICEVOX_SERVER_URL").unwrap_or_default();
+        // 音声ファイルごとに正規化する場合のみ、目標値が音声に影響する
+        let loudness_mode = loudness::Mode::from_env();
+        let loudness_target =
+            (loudness_mode == loudness::Mode::Clip).then(loudness::Target::from_env);
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for section in sections.iter_mut() {
+            let markup = section.style.uses_markup();
+            for content in section.contents.iter_mut() {
+                // 読み上げるテキストは装飾を解釈するかで変わる
+                let base = job::fingerprint(&(
+                    content.voice_id,
+                    &default_voice_id,
+                    subtitle::reading_text(&content.text, markup),
+                    &content.prosody,
+                    (&server_url, loudness_mode, loudness_target),
+                ));
+                let count = counts.entry(base.clone()).or_default();
+                *count += 1;
+                content.key = format!("{}-{}", base, count);
+            }
+        }
+    }
+
+    // エラーにヘッダーの位置(ファイル名:行番号)を付ける
+    fn with_source(source: Option<&str>, e: String) -> String {
+        match source {
+            Some(source) => format!("{}: {}", source, e),
+            None => e,
+        }
+    }
+
+    // スライドのパスを絶対パスにする
+    // PDFのページ指定(deck.pdf#3)はファイルのパスのみを解決する
+    fn resolve_path(filename: &str, base_dir: &Path) -> String {
+        let (path, page) = match pdf::Page::parse(filename) {
+            Ok(Some(page)) if filename.contains('#') => (page.path, format!("#{}", page.number)),
+            _ => (filename.to_string(), "".to_string()),
+        };
+        let path = utils::absolute_path(Path::new(&path), base_dir);
+        format!("{}{}", path.to_string_lossy(), page)
+    }
+
+    // ワイルドカードに一致したファイル、またはPDFのページごとのセクションに展開する
+    // 本文は空行で区切った段落を、ファイルの順に一つずつ割り当てる
+    fn expand(self, paths: Vec<String>) -> Result<Vec<Section>, String> {
+        if paths.is_empty() {
+            return Err(format!("no slide matches: {}", self.filename));
+        }
+
+        let mut paragraphs: Vec<Vec<Content>> = vec![vec![]];
+        for content in self.contents.iter() {
+            if content.text.is_empty() {
+                paragraphs.push(vec![]);
+            } else if let Some(paragraph) = paragraphs.last_mut() {
+                paragraph.push(content.clone());
+            }
+        }
+        paragraphs.retain(|paragraph| !paragraph.is_empty());
+        if paragraphs.len() != paths.len() {
+            return Err(format!(
+                "{} paragraphs for {} slides matched by {}",
+                paragraphs.len(),
+                paths.len(),
+                self.filename
+            ));
+        }
+
+        Ok(paths
+            .into_iter()
+            .zip(paragraphs)
+            .map(|(path, contents)| {
+                let mut section = self.clone();
+                section.filename = path;
+                section.contents = contents;
+                section
+            })
+            .collect())
+    }
+
+    fn parse_vec(resource_text: PathBuf) -> Result<Vec<Section>, String> {
+        let extension = resource_text
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        // ドキュメント全体のテーマ
+        // 環境変数で指定し、!theme ディレクティブで上書きできる
+        let mut theme = match std::env::var("DEFAULT_THEME_FILE_PATH") {
+            Ok(path) if !path.is_empty() => video_option::Op::from_file(Path::new(&path))?,
+            _ => video_option::Op::empty(),
+        };
+
+        // PowerPointはテキストではないため、読み込む前に処理する
+        if extension == "pptx" {
+            return pptx::create_vec(&resource_text, theme);
+        }
+
+        let mut f = std::fs::File::open(&resource_text).map_err(|e| e.to_string())?;
         // まずは行区切りに分割
         let mut all_text = String::new();
         std::io::Read::read_to_string(&mut f, &mut all_text).map_err(|e| e.to_string())?;
@@ -73,13 +315,33 @@ impl Section {
         // UTF-8 BOM: \u{feff}, UTF-16 LE: 0xFF 0xFE, UTF-16 BE: 0xFE 0xFF, etc.
         all_text = all_text.trim_start_matches("\u{feff}").to_string();
 
-        // Platform-independent line splitting (handles both \r\n and \n)
-        let split_text = all_text.lines().collect::<Vec<&str>>();
         let mut texts = vec![];
         let mut inner_text = Section::blanc();
 
-        for text in split_text {
-            let target_text = text.trim();
+        // !pdf ディレクティブで指定したPDF
+        // [#ページ番号] のヘッダーでページを指定できる
+        let mut deck: Option<String> = None;
+
+        // スクリプト中の相対パスはスクリプトのディレクトリを基準にする
+        let current_dir = std::env::current_dir().map_err(|e| e.to_string())?;
+        let resource_text = utils::absolute_path(&resource_text, &current_dir);
+        let base_dir = resource_text.parent().unwrap_or(&current_dir);
+
+        // Markdown形式、YAML/JSON形式のスクリプト
+        match extension.as_str() {
+            "md" => return markdown::create_vec(&all_text, theme, base_dir),
+            "yaml" | "yml" | "json" => {
+                return script::Script::parse(&all_text, extension == "json")?
+                    .into_sections(theme, base_dir);
+            }
+            _ => {}
+        }
+
+        // !include で指定したファイルを展開した行
+        let lines = SourceLine::expand(&resource_text, &all_text, &mut vec![])?;
+
+        for line in lines {
+            let target_text = line.text.trim();
             if target_text.is_empty() {
                 // Keep empty lines in content if we're already in a section with content
                 if !inner_text.filename.is_empty() && !inner_text.contents.is_empty() {
@@ -88,7 +350,14 @@ impl Section {
                 continue;
             }
 
-            if target_text.starts_with("[") && target_text.ends_with("]") {
+            if let Some(path) = target_text.strip_prefix("!theme ") {
+                // 以降のセクションに適用するテーマ
+                let path = utils::absolute_path(Path::new(path.trim()), line.dir());
+                theme = video_option::Op::from_file(&path).map_err(|e| line.error(e))?;
+            } else if let Some(path) = target_text.strip_prefix("!pdf ") {
+                // 相対パスは記述したファイルのディレクトリを基準にする
+                deck = Some(Section::resolve_path(path.trim(), line.dir()));
+            } else if target_text.starts_with("[") && target_text.ends_with("]") {
                 // 新規: タイトルがあり、かつ、コンテンツがある場合は、新しいセクションとする
                 // セクションを追加し、新しいテキストを作成
                 if !inner_text.filename.is_empty() {
@@ -97,10 +366,21 @@ impl Section {
                     inner_text = Section::blanc();
                 }
 
-                let filename = target_text[1..target_text.len() - 1].trim();
-                let path_filename = PathBuf::from(filename).to_string_lossy().into_owned();
+                let (filename, attributes) =
+                    Section::parse_header(&target_text[1..target_text.len() - 1]);
+                let path_filename = match deck.as_deref() {
+                    Some(deck) if filename.starts_with('#') => format!("{}{}", deck, filename),
+                    _ => Section::resolve_path(filename, line.dir()),
+                };
 
                 inner_text.filename = path_filename;
+                inner_text.style = theme.clone();
+                inner_text.source = Some(line.location());
+                for (key, value) in attributes {
+                    inner_text
+                        .apply_attribute(&key, &value, line.dir())
+                        .map_err(|e| line.error(e))?;
+                }
             } else if target_text.starts_with("#") {
                 // このセクションの以前のタイトルを上書きする可能性がある
                 // タイトルが複数ある場合は、最後のタイトルが採用される
@@ -114,12 +394,11 @@ impl Section {
                     let (voice_id, text) = if target_text.starts_with("@") {
                         let mut split_text = target_text.splitn(2, " ");
                         println!("split_text: {:?}", split_text);
-                        let voice_id = split_text
-                            .next()
-                            .unwrap()
-                            .trim_start_matches("@")
-                            .parse::<i32>()
-                            .ok();
+                        let voice_id = split_text.next().unwrap().trim_start_matches("@");
+                        let voice_id = voice_id.parse::<i32>().map_err(|e| {
+                            line.error(format!("invalid voice id {}: {}", voice_id, e))
+                        })?;
+                        let voice_id = Some(voice_id);
                         let text = split_text.next().unwrap_or("").to_string();
                         (voice_id, text)
                     } else {
@@ -141,22 +420,100 @@ impl Section {
         Ok(texts)
     }
 
+    // ヘッダー[ファイル名 | 属性=値, 属性=値]をファイル名と属性に分割
+    // 括弧と引用符の中の , は値の一部とする
+    fn parse_header(header: &str) -> (&str, Vec<(String, String)>) {
+        let mut split = header.splitn(2, '|');
+        let filename = split.next().unwrap_or("").trim();
+        let mut attributes = utils::split_attributes(split.next().unwrap_or(""))
+            .into_iter()
+            .filter_map(|attribute| {
+                let (key, value) = attribute.split_once('=')?;
+                Some((key.trim().to_lowercase(), value.trim().to_string()))
+            })
+            .collect::<Vec<(String, String)>>();
+        // テーマは他の属性より先に適用し、個別の属性で上書きできるようにする
+        attributes.sort_by_key(|(key, _)| key != "theme");
+
+        (filename, attributes)
+    }
+
+    // ヘッダーの属性をセクションに反映
+    // スタイルの属性はテーマに上書きされる
+    // base_dir: テーマの相対パスの基準となるディレクトリ
+    pub fn apply_attribute(
+        &mut self,
+        key: &str,
+        value: &str,
+        base_dir: &Path,
+    ) -> Result<(), String> {
+        match key {
+            "transition" => {
+                self.transition = transition::Transition::parse(value)?;
+            }
+            "theme" => {
+                let path = utils::absolute_path(Path::new(value), base_dir);
+                self.style.merge(&video_option::Op::from_file(&path)?);
+            }
+            _ => {
+                // それ以外はスタイルの上書きとして扱う
+                if !self.style.set(key, value)? {
+                    warn!("unknown section attribute: {}={}", key, value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // チャプターのタイトル
+    // タイトルがない場合はスライドのファイル名
+    pub fn chapter_title(&self) -> String {
+        match self.title.as_deref() {
+            Some(title) if !title.is_empty() => title.to_string(),
+            _ => Path::new(&self.filename)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or(self.filename.clone()),
+        }
+    }
+
+    // セクションの長さ(秒)
+    // 音声の長さの合計
+    pub fn duration(&self) -> f64 {
+        self.contents
+            .iter()
+            .filter_map(|content| self.voices.get(&content.key))
+            .map(|voice_data| voice_data.duration.num_milliseconds() as f64 / 1000.0)
+            .sum()
+    }
+
     // テキストコンテンツの音声化
     // Supported:
     // - VoicevoxAPIを使って音声化
-    pub async fn create_voices(&mut self) -> Result<(), String> {
+    // job: 完了した音声は再利用し、生成した音声を記録する
+    pub async fn create_voices(&mut self, job: &mut job::Job) -> Result<(), String> {
         if self.contents.is_empty() {
             return Err("No content to  to voice".to_string());
         }
 
         let output_dir = utils::target_path_from_env("DEFAULT_OUTPUT_VOICE_FILE_DIR");
         for content in self.contents.iter() {
+            if let Some(voice_data) = job.voice(&content.key) {
+                info!("voice reused: {}", voice_data.filepath);
+                self.voices.insert(content.key.clone(), voice_data);
+                continue;
+            }
+
             let output_filepath = output_dir
                 .join(format!("{}.wav", content.key))
                 .to_string_lossy()
                 .into_owned();
             let voice_data = match voice::Data::new(content.voice_id, &output_filepath)
-                .create_voice(content.text.as_str())
+                .create_voice(
+                    &subtitle::reading_text(&content.text, self.style.uses_markup()),
+                    &content.prosody,
+                )
                 .await
             {
                 Ok(voice_data) => voice_data,
@@ -164,6 +521,7 @@ impl Section {
                     return Err(format!("Error: {}", e));
                 }
             };
+            job.set_voice(&content.key, &voice_data)?;
             self.voices.insert(content.key.clone(), voice_data);
         }
 
@@ -174,26 +532,57 @@ impl Section {
     // セッションの音声ごとに動画を生成
     // 画像とテキストと音声を組み合わせて動画を生成
     // 音声ごとに生成した動画を連結し、セッションの動画を生成する
-    pub async fn create_video(&mut self) -> Result<(), String> {
-        let mut parts = vec![];
-        for content in self.contents.iter() {
-            let voice_data = match self.voices.get(&content.key) {
-                Some(voice_data) => voice_data,
-                None => {
-                    warn!("voice data not found: {:?}", content.key);
-                    continue;
-                }
-            };
+    // profile: 出力プロファイル。Noneの場合はテーマの解像度で生成する
+    // job: 入力が変わっていない動画は再利用し、生成した動画を記録する
+    pub async fn create_video(
+        &mut self,
+        profile: Option<&profile::Profile>,
+        job: &mut job::Job,
+    ) -> Result<(), String> {
+        let part_options = self.create_part_options(profile)?;
+        self.parts.clear();
+
+        // プロファイルごとに別のファイルに出力する
+        let profile_name = profile
+            .map(|profile| profile.name.as_str())
+            .unwrap_or("default");
+        let section_key = format!(
+            "section-{}-{}",
+            job::fingerprint(
+                &self
+                    .contents
+                    .iter()
+                    .map(|content| content.key.as_str())
+                    .collect::<Vec<&str>>()
+            ),
+            profile_name
+        );
+        let slide = self.slide_fingerprint();
+
+        if video::RenderMode::from_env() == video::RenderMode::Section {
+            return self
+                .create_video_single(part_options, section_key, &slide, job)
+                .await;
+        }
 
-            // 動画生成のためのオプション
-            let mut op = video_option::Op::default();
-            op.set_word(content.text.as_str());
+        let mut parts = vec![];
+        let mut fingerprints = vec![];
+        for (key, voice_data, op) in part_options {
+            let part_key = format!("{}-{}", key, profile_name);
+            let fingerprint = job::fingerprint(&(&slide, &voice_data.filepath, &op));
+            fingerprints.push(fingerprint.clone());
+            if let Some(output_filepath) = job.output(&part_key, &fingerprint) {
+                info!("part reused: {}", output_filepath);
+                self.parts.insert(key, output_filepath.clone());
+                parts.push(output_filepath);
+                continue;
+            }
 
             // 動画生成のためのパラメータ
             let (video_args, output_filepath) = video::create_args(
-                content.key.to_string(),
+                part_key.clone(),
                 self.filename.clone(),
-                voice_data.clone(),
+                voice_data,
                 Some(op),
             );
 
@@ -202,6 +591,8 @@ impl Section {
                 Ok(result) => {
                     // 動画生成成功したら、パスを保存
                     info!("video created: {:?}", result);
+                    job.set_output(&part_key, &fingerprint, &output_filepath)?;
+                    self.parts.insert(key, output_filepath.clone());
                     parts.push(output_filepath);
                 }
                 Err(e) => {
@@ -210,11 +601,20 @@ impl Section {
             };
         }
 
+        // テキストごとの動画がすべて同じであれば、連結した動画も再利用する
+        let fingerprint = job::fingerprint(&fingerprints);
+        if let Some(output_filepath) = job.output(&section_key, &fingerprint) {
+            info!("section video reused: {}", output_filepath);
+            self.video = Some(output_filepath);
+            return Ok(());
+        }
+
         let (concat_file, output_file) = video::create_output_files(parts);
 
         match video::concat(concat_file, output_file).await {
             Ok(output_video_filepath) => {
                 info!("video concated: {:?}", output_video_filepath);
+                job.set_output(&section_key, &fingerprint, &output_video_filepath)?;
                 self.video = Some(output_video_filepath);
             }
             Err(e) => {
@@ -224,6 +624,159 @@ impl Section {
 
         Ok(())
     }
+
+    // セクション全体を一つのffmpegジョブで動画化
+    async fn create_video_single(
+        &mut self,
+        part_options: Vec<(String, voice::Data, video_option::Op)>,
+        key: String,
+        slide: &str,
+        job: &mut job::Job,
+    ) -> Result<(), String> {
+        if part_options.is_empty() {
+            return Err("No voice to video".to_string());
+        }
+
+        let parts = part_options
+            .into_iter()
+            .map(|(_, voice_data, op)| (voice_data, op))
+            .collect::<Vec<(voice::Data, video_option::Op)>>();
+        let fingerprint = job::fingerprint(&(
+            slide,
+            parts
+                .iter()
+                .map(|(voice_data, op)| (&voice_data.filepath, op))
+                .collect::<Vec<_>>(),
+        ));
+        if let Some(output_filepath) = job.output(&key, &fingerprint) {
+            info!("section video reused: {}", output_filepath);
+            self.video = Some(output_filepath);
+            return Ok(());
+        }
+
+        let (video_args, output_filepath) =
+            video::create_section_args(key.clone(), self.filename.clone(), parts);
+
+        match video::create_part(video_args).await {
+            Ok(result) => {
+                info!("section video created: {:?}", result);
+                job.set_output(&key, &fingerprint, &output_filepath)?;
+                self.video = Some(output_filepath);
+            }
+            Err(e) => {
+                return Err(format!("section create Error: {}", e));
+            }
+        };
+
+        Ok(())
+    }
+
+    // スライドのファイル名と更新日時
+    // スライドを差し替えた場合は動画を生成し直す
+    fn slide_fingerprint(&self) -> String {
+        let modified = std::fs::metadata(&self.filename)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|modified| modified.as_millis())
+            .unwrap_or_default();
+        format!("{}:{}", self.filename, modified)
+    }
+
+    // テキストごとの動画生成オプションを生成
+    // (コンテンツkey, 音声, オプション)の組を返す
+    fn create_part_options(
+        &self,
+        profile: Option<&profile::Profile>,
+    ) -> Result<Vec<(String, voice::Data, video_option::Op)>, String> {
+        let mut part_options = vec![];
+        // 動きをセクション全体で連続させるための経過時間
+        let total = self.duration();
+        let mut elapsed = 0.0;
+        let default_motion =
+            video_option::Motion::parse(&std::env::var("DEFAULT_MOTION").unwrap_or_default())?;
+        let characters = character::Registry::from_env()?;
+        let speakers = speaker::Registry::from_env()?;
+        let render_mode = video::RenderMode::from_env();
+        // ASS字幕はプロファイルごとに別のファイルに出力する
+        let profile_name = profile
+            .map(|profile| profile.name.as_str())
+            .unwrap_or("default");
+        for content in self.contents.iter() {
+            let voice_data = match self.voices.get(&content.key) {
+                Some(voice_data) => voice_data,
+                None => {
+                    warn!("voice data not found: {:?}", content.key);
+                    continue;
+                }
+            };
+
+            // 動画生成のためのオプション
+            let mut op = video_option::Op {
+                motion: default_motion,
+                ..Default::default()
+            };
+            // プロファイルの字幕の大きさと余白は、テーマやヘッダーの指定で上書きできる
+            // 解像度は常にプロファイルに従う
+            if let Some(profile) = profile {
+                op.merge(&profile.to_op());
+            }
+            // 話者ごとの字幕スタイルは既定値として、テーマやヘッダーの指定で上書きできる
+            let voice_id = voice::resolve_voice_id(voice_data.voice_id);
+            if let Some(style) = speakers.get(voice_id) {
+                op.merge(&style.to_op());
+            }
+            op.merge(&self.style);
+            if let Some(profile) = profile {
+                op.w = Some(profile.w);
+                op.h = Some(profile.h);
+            }
+            op.set_word(content.text.as_str());
+            op.title = self.title.clone();
+            op.motion_offset = Some(elapsed);
+            op.motion_total = Some(total);
+            // 話者のキャラクターと口の開閉
+            if let Some(avatar) = characters.get(voice_id) {
+                op.character = Some(character::Overlay::from_wav(
+                    avatar,
+                    &voice_data.filepath,
+                    video_option::FPS,
+                )?);
+            }
+            let duration = voice_data.duration.num_milliseconds() as f64 / 1000.0;
+            // ASS字幕(ルビ、文字色、カラオケ表示)
+            if op.uses_subtitle_file() {
+                // カラオケ表示はモーラのタイミングを使う
+                let timings = match op.caption_mode {
+                    Some(video_option::CaptionMode::Karaoke) => match voice_data.query() {
+                        Some(query) => Some(query.mora_timings()),
+                        None => {
+                            warn!("audio query not found, disable karaoke: {:?}", content.key);
+                            None
+                        }
+                    },
+                    _ => None,
+                };
+                // セクション単位で生成する場合はセクション内の位置に表示する
+                let start = match render_mode {
+                    video::RenderMode::Section => elapsed,
+                    video::RenderMode::Part => 0.0,
+                };
+                let subtitle_file = utils::target_path_from_env("DEFAULT_OUTPUT_VIDEO_FILE_DIR")
+                    .join(format!("{}-{}.ass", content.key, profile_name))
+                    .to_string_lossy()
+                    .into_owned();
+                subtitle::caption_document(&op, timings.as_deref(), start, duration)
+                    .save(&subtitle_file)?;
+                op.subtitle_file = Some(subtitle_file);
+            }
+            elapsed += duration;
+
+            part_options.push((content.key.to_string(), voice_data.clone(), op));
+        }
+
+        Ok(part_options)
+    }
 }
 
 #[cfg(test)]
@@ -248,6 +801,131 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_header() {
+        let (filename, attributes) =
+            Section::parse_header(r"C:\slides\1.png | transition=crossfade:1.0");
+        assert_eq!(filename, r"C:\slides\1.png");
+        assert_eq!(
+            attributes,
+            vec![("transition".to_string(), "crossfade:1.0".to_string())]
+        );
+
+        let (filename, attributes) = Section::parse_header("slides/2.png");
+        assert_eq!(filename, "slides/2.png");
+        assert!(attributes.is_empty());
+
+        let (_, attributes) =
+            Section::parse_header("slides/3.png | motion=zoom_in, theme=dark.toml");
+        assert_eq!(attributes[0].0, "theme");
+
+        // ffmpegの式の , では分割しない
+        let (_, attributes) =
+            Section::parse_header("slides/4.png | x=if(gt(t,1),10,20), font_color=black");
+        assert_eq!(
+            attributes,
+            vec![
+                ("x".to_string(), "if(gt(t,1),10,20)".to_string()),
+                ("font_color".to_string(), "black".to_string()),
+            ]
+        );
+
+        // 相対パスはスクリプトのディレクトリを基準にする
+        let base_dir = Path::new("/work/scripts");
+        assert_eq!(
+            Section::resolve_path("./slides/1.png", base_dir),
+            "/work/scripts/slides/1.png"
+        );
+        assert_eq!(
+            Section::resolve_path("../decks/deck.pdf#3", base_dir),
+            "/work/decks/deck.pdf#3"
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(dir.join("chapter")).unwrap();
+        std::fs::write(
+            dir.join("chapter/ch2.txt"),
+            "[slide2.png]\n@2 二章です。\n!include ../footer.txt",
+        )
+        .unwrap();
+        std::fs::write(dir.join("footer.txt"), "# おわり").unwrap();
+        let main = dir.join("main.txt");
+        let text = "[slide1.png]\n!include chapter/ch2.txt\n";
+
+        let lines = SourceLine::expand(&main, text, &mut vec![]).unwrap();
+        assert_eq!(
+            lines
+                .iter()
+                .map(|line| line.location())
+                .collect::<Vec<String>>(),
+            vec![
+                format!("{}:1", main.display()),
+                format!("{}:1", dir.join("chapter/ch2.txt").display()),
+                format!("{}:2", dir.join("chapter/ch2.txt").display()),
+                format!("{}:1", dir.join("footer.txt").display()),
+            ]
+        );
+        assert_eq!(
+            Section::resolve_path("slide2.png", lines[1].dir()),
+            dir.join("chapter/slide2.png").to_string_lossy()
+        );
+
+        // 循環している場合は、循環させた行の位置とともに一度だけエラーを返す
+        std::fs::write(dir.join("footer.txt"), "!include main.txt").unwrap();
+        std::fs::write(&main, text).unwrap();
+        let e = SourceLine::expand(&main, text, &mut vec![]).unwrap_err();
+        assert_eq!(
+            e,
+            format!(
+                "{}:1: include cycle: {} -> {} -> {} -> {}",
+                dir.join("footer.txt").display(),
+                main.display(),
+                dir.join("chapter/ch2.txt").display(),
+                dir.join("footer.txt").display(),
+                main.display()
+            )
+        );
+
+        // 話者の誤りは記述した行の位置とともにエラーになる
+        std::fs::write(dir.join("footer.txt"), "@abc おわり").unwrap();
+        std::fs::write(&main, text).unwrap();
+        let e = Section::parse_vec(main.clone()).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(e.starts_with(&format!(
+            "{}:1: invalid voice id abc",
+            dir.join("footer.txt").display()
+        )));
+    }
+
+    #[test]
+    fn test_theme_path() {
+        // テーマの相対パスはカレントディレクトリではなく、記述したファイルを基準にする
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(dir.join("themes")).unwrap();
+        std::fs::write(dir.join("themes/dark.toml"), "font_color = \"white\"").unwrap();
+        std::fs::write(dir.join("themes/red.toml"), "font_color = \"red\"").unwrap();
+        std::fs::write(
+            dir.join("main.txt"),
+            "!theme themes/dark.toml\n[slide1.png]\n一枚目\n[slide2.png | theme=themes/red.toml]\n二枚目\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.md"),
+            "<!-- theme=themes/dark.toml -->\n![](slide1.png)\n一枚目\n",
+        )
+        .unwrap();
+
+        let sections = Section::parse_vec(dir.join("main.txt")).unwrap();
+        let markdown = Section::parse_vec(dir.join("main.md")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(sections[0].style.font_color.as_deref(), Some("white"));
+        assert_eq!(sections[1].style.font_color.as_deref(), Some("red"));
+        assert_eq!(markdown[0].style.font_color.as_deref(), Some("white"));
+    }
+
     #[test]
     fn test_remove_file_all() {
         remove_files("video");
@@ -269,7 +947,7 @@ mod tests {
         section
             .contents
             .push(Content::new(None, "テスト".to_string()));
-        let result = section.create_voices().await;
+        let result = section.create_voices(&mut job::Job::disabled()).await;
         assert!(result.is_ok());
     }
 
@@ -289,7 +967,7 @@ mod tests {
         info!("resource data for section: {:?}", sections);
 
         for section in sections.iter_mut() {
-            match section.create_voices().await {
+            match section.create_voices(&mut job::Job::disabled()).await {
                 Ok(_) => {
                     info!("to_voices: {:?}", section);
                 }
@@ -298,7 +976,7 @@ mod tests {
                 }
             };
 
-            match section.create_video().await {
+            match section.create_video(None, &mut job::Job::disabled()).await {
                 Ok(_) => {
                     info!("inner create_video: {:?}", section);
                 }
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::slide::{
+    utils,
+    video_option::{self, CaptionAlign, Op},
+};
+
+// 話者ごとの字幕スタイル
+// 掛け合いの動画で誰が話しているかを分かりやすくする
+
+// 字幕を表示する画面の側
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Style {
+    // 字幕の先頭に表示する名前
+    pub name: Option<String>,
+    pub font_color: Option<String>,
+    pub border_color: Option<String>,
+    pub box_color: Option<String>,
+    pub side: Option<Side>,
+}
+
+impl Style {
+    // 字幕のオプションに上書きする部分的なオプション
+    pub fn to_op(&self) -> Op {
+        let mut op = Op::empty();
+        op.label = self.name.clone();
+        op.font_color = self
+            .font_color
+            .as_deref()
+            .map(video_option::normalize_color);
+        op.border_color = self
+            .border_color
+            .as_deref()
+            .map(video_option::normalize_color);
+        op.box_color = self.box_color.as_deref().map(video_option::normalize_color);
+        op.caption_align = self.side.map(|side| match side {
+            Side::Left => CaptionAlign::Left,
+            Side::Center => CaptionAlign::Center,
+            Side::Right => CaptionAlign::Right,
+        });
+        op
+    }
+}
+
+// ボイスIDごとの字幕スタイル
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    styles: HashMap<i32, Style>,
+}
+
+impl Registry {
+    // 環境変数DEFAULT_SPEAKER_FILE_PATHのJSONを読み込む
+    // 例) { "2": { "name": "めたん", "font_color": "#F8BBD0", "side": "left" } }
+    // 未指定の場合は話者ごとのスタイルなし
+    pub fn from_env() -> Result<Registry, String> {
+        match std::env::var("DEFAULT_SPEAKER_FILE_PATH") {
+            Ok(path) if !path.is_empty() => Registry::from_file(&path),
+            _ => Ok(Registry::default()),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Registry, String> {
+        let styles = utils::read_voice_map(path, "speakers")?;
+        Ok(Registry { styles })
+    }
+
+    pub fn get(&self, voice_id: i32) -> Option<&Style> {
+        self.styles.get(&voice_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style_to_op() {
+        let styles = utils::parse_voice_map(
+            r##"{
+                "2": { "name": "めたん", "font_color": "#F8BBD0", "side": "left" },
+                "3": { "name": "ずんだもん", "border_color": "0x2E7D32", "side": "right" }
+            }"##,
+        )
+        .unwrap();
+        let registry = Registry { styles };
+
+        let op = registry.get(2).unwrap().to_op();
+        assert_eq!(op.label.as_deref(), Some("めたん"));
+        assert_eq!(op.font_color.as_deref(), Some("0xF8BBD0"));
+        assert_eq!(op.caption_align, Some(CaptionAlign::Left));
+        assert!(op.border_color.is_none());
+
+        let op = registry.get(3).unwrap().to_op();
+        assert_eq!(op.caption_align, Some(CaptionAlign::Right));
+        assert!(registry.get(14).is_none());
+    }
+}
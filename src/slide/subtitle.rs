@@ -0,0 +1,679 @@
+use log::warn;
+
+use crate::slide::{
+    video_option::{self, CaptionAlign, CaptionMode, CaptionPosition, Op},
+    voicevox::MoraTiming,
+};
+
+// ASS(Advanced SubStation Alpha)字幕の生成
+// ffmpegのsubtitlesフィルタ(libass)で焼き込む
+
+// ffmpegの色指定をASSの色(&HAABBGGRR)に変換する
+// 0xRRGGBB, 0xRRGGBBAA, #RRGGBB, 色名@透明度 に対応
+pub fn ass_color(value: &str) -> String {
+    let (color, alpha) = match value.split_once('@') {
+        Some((color, alpha)) => (color.trim(), alpha.trim().parse::<f64>().unwrap_or(1.0)),
+        None => (value.trim(), 1.0),
+    };
+
+    let hex = color
+        .strip_prefix("0x")
+        .or_else(|| color.strip_prefix('#'))
+        .map(|hex| hex.to_string())
+        .unwrap_or_else(|| {
+            match color.to_lowercase().as_str() {
+                "white" => "FFFFFF",
+                "black" => "000000",
+                "red" => "FF0000",
+                "green" => "008000",
+                "blue" => "0000FF",
+                "yellow" => "FFFF00",
+                "orange" => "FFA500",
+                "pink" => "FFC0CB",
+                "gray" | "grey" => "808080",
+                other => {
+                    warn!("unknown color for ass, use white: {}", other);
+                    "FFFFFF"
+                }
+            }
+            .to_string()
+        });
+
+    let channel =
+        |i: usize| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("FF"), 16).unwrap_or(255);
+    let (r, g, b) = (channel(0), channel(2), channel(4));
+    // ffmpegのアルファは不透明度、ASSは透明度
+    let opacity = if hex.len() >= 8 {
+        channel(6) as f64 / 255.0 * alpha
+    } else {
+        alpha
+    };
+    let a = ((1.0 - opacity.clamp(0.0, 1.0)) * 255.0).round() as u8;
+
+    format!("&H{:02X}{:02X}{:02X}{:02X}", a, b, g, r)
+}
+
+// 文字色を変える上書きタグ(\c&HBBGGRR&)
+fn color_tag(value: &str) -> String {
+    let color = ass_color(value);
+    format!("\\c&H{}&", &color[4..])
+}
+
+// 秒をASSの時刻(H:MM:SS.cc)に変換する
+pub fn ass_time(seconds: f64) -> String {
+    let centiseconds = (seconds.max(0.0) * 100.0).round() as i64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centiseconds / 360000,
+        centiseconds / 6000 % 60,
+        centiseconds / 100 % 60,
+        centiseconds % 100
+    )
+}
+
+// 字幕のテキストをエスケープする
+// {}は上書きタグ、\は制御文字として扱われるため全角に置き換える
+pub fn escape_text(text: &str) -> String {
+    text.replace('\\', "＼")
+        .replace('{', "｛")
+        .replace('}', "｝")
+}
+
+// ffmpegのフィルタ引数に渡すパスをエスケープする
+// Windowsのパス(C:\...)は区切り文字を/に揃え、:をエスケープする
+pub fn filter_path(path: &str) -> String {
+    path.replace('\\', "/")
+        .replace(':', "\\:")
+        .replace('\'', "\\'")
+}
+
+// 字幕のスタイル
+#[derive(Debug, Clone)]
+pub struct Style {
+    pub name: String,
+    pub font_name: String,
+    pub font_size: String,
+    // 塗りの色(カラオケでは塗り終わった色)
+    pub primary_color: String,
+    // カラオケで塗られる前の色
+    pub secondary_color: String,
+    pub outline_color: String,
+    pub back_color: String,
+    // 1: 縁取り+影, 3: 背景ボックス
+    pub border_style: i32,
+    pub outline: i32,
+    pub shadow: i32,
+    // テンキー配置(1〜9)
+    pub alignment: i32,
+    pub margin_l: i32,
+    pub margin_r: i32,
+    pub margin_v: i32,
+}
+
+impl Style {
+    // 動画生成オプションからスタイルを作る
+    pub fn from_op(name: &str, op: &Op) -> Style {
+        let font_color = op.font_color.as_deref().unwrap_or("white");
+        // カラオケ表示の場合のみ塗り終わった色を変える
+        let highlight_color = match op.caption_mode {
+            Some(CaptionMode::Karaoke) => op.highlight_color.as_deref().unwrap_or(font_color),
+            _ => font_color,
+        };
+        let border_color = op.border_color.as_deref().unwrap_or("black");
+        let margin_x = op.margin_x.unwrap_or(0);
+
+        let column = match op.caption_align.unwrap_or(CaptionAlign::Center) {
+            CaptionAlign::Left => 1,
+            CaptionAlign::Center => 2,
+            CaptionAlign::Right => 3,
+        };
+        let row = match op.caption_position.unwrap_or(CaptionPosition::Bottom) {
+            CaptionPosition::Bottom | CaptionPosition::Custom => 0,
+            CaptionPosition::Center => 3,
+            CaptionPosition::Top => 6,
+        };
+
+        // 背景ボックスがある場合はボックス表示にする
+        let (border_style, outline_color, outline) = match op.box_color.as_deref() {
+            Some(box_color) => (3, ass_color(box_color), op.box_border_w.unwrap_or(20) / 2),
+            None => (1, ass_color(border_color), op.border_w.unwrap_or(10) / 2),
+        };
+        let (back_color, shadow) = match op.shadow_color.as_deref() {
+            Some(shadow_color) => (ass_color(shadow_color), op.shadow_y.unwrap_or(4)),
+            None => (ass_color("black@0"), 0),
+        };
+
+        Style {
+            name: name.to_string(),
+            font_name: op.font_name.clone().unwrap_or("Noto Sans JP".to_string()),
+            font_size: op.font_size.clone().unwrap_or("36".to_string()),
+            primary_color: ass_color(highlight_color),
+            secondary_color: ass_color(font_color),
+            outline_color,
+            back_color,
+            border_style,
+            outline,
+            shadow,
+            alignment: row + column,
+            margin_l: margin_x,
+            margin_r: margin_x,
+            margin_v: op.margin_y.unwrap_or(50),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "Style: {},{},{},{},{},{},{},-1,0,0,0,100,100,0,0,{},{},{},{},{},{},{},1",
+            self.name,
+            self.font_name,
+            self.font_size,
+            self.primary_color,
+            self.secondary_color,
+            self.outline_color,
+            self.back_color,
+            self.border_style,
+            self.outline,
+            self.shadow,
+            self.alignment,
+            self.margin_l,
+            self.margin_r,
+            self.margin_v,
+        )
+    }
+}
+
+// 字幕の表示単位
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub start: f64,
+    pub end: f64,
+    pub style: String,
+    // エスケープ済みのテキスト(上書きタグを含む)
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub width: i16,
+    pub height: i16,
+    pub styles: Vec<Style>,
+    pub events: Vec<Event>,
+}
+
+impl Document {
+    pub fn new(width: i16, height: i16) -> Document {
+        Document {
+            width,
+            height,
+            styles: vec![],
+            events: vec![],
+        }
+    }
+
+    pub fn to_ass(&self) -> String {
+        let mut lines = vec![
+            "[Script Info]".to_string(),
+            "ScriptType: v4.00+".to_string(),
+            format!("PlayResX: {}", self.width),
+            format!("PlayResY: {}", self.height),
+            "WrapStyle: 0".to_string(),
+            "ScaledBorderAndShadow: yes".to_string(),
+            "".to_string(),
+            "[V4+ Styles]".to_string(),
+            "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding".to_string(),
+        ];
+        lines.extend(self.styles.iter().map(|style| style.to_line()));
+        lines.extend([
+            "".to_string(),
+            "[Events]".to_string(),
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text"
+                .to_string(),
+        ]);
+        lines.extend(self.events.iter().map(|event| {
+            format!(
+                "Dialogue: 0,{},{},{},,0,0,0,,{}",
+                ass_time(event.start),
+                ass_time(event.end),
+                event.style,
+                event.text
+            )
+        }));
+        lines.push("".to_string());
+
+        lines.join("\n")
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_ass()).map_err(|e| e.to_string())
+    }
+}
+
+// 発声しない文字(句読点、空白、記号)
+// 長音(ー)は一つのモーラとして発声されるため含めない
+fn is_silent(c: char) -> bool {
+    c.is_whitespace()
+        || c.is_ascii_punctuation()
+        || "、。，．・！？「」『』（）【】〈〉《》〜…―‥：；".contains(c)
+}
+
+// モーラのタイミングから、文字ごとに塗りが進むカラオケタグ付きのテキストを作る
+// 漢字かな混じりの文字とモーラは一致しないため、発声する文字数とモーラ数の比で割り当てる
+pub fn karaoke_text(text: &str, timings: &[MoraTiming]) -> String {
+    let speakable = text.chars().filter(|c| !is_silent(*c)).count();
+    if timings.is_empty() || speakable == 0 {
+        return escape_text(text);
+    }
+
+    let centiseconds = |seconds: f64| (seconds * 100.0).round() as i64;
+    let mut result = String::new();
+    let mut previous = centiseconds(timings[0].start);
+    if previous > 0 {
+        result.push_str(&format!("{{\\k{}}}", previous));
+    }
+
+    let mut index = 0;
+    for c in text.chars() {
+        if is_silent(c) {
+            // 直前の文字と一緒に塗る
+            result.push_str(&escape_text(&c.to_string()));
+            continue;
+        }
+
+        // この文字までに発声されるモーラ
+        let mora = ((index + 1) * timings.len()).div_ceil(speakable) - 1;
+        let end = centiseconds(timings[mora.min(timings.len() - 1)].end);
+        result.push_str(&format!("{{\\kf{}}}", (end - previous).max(0)));
+        result.push_str(&escape_text(&c.to_string()));
+        previous = end;
+        index += 1;
+    }
+
+    result
+}
+
+// 字幕の装飾付きテキストの区間
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    // ルビ(読み)
+    pub ruby: Option<String>,
+    // 文字色(ffmpegの形式)
+    pub color: Option<String>,
+}
+
+// ルビの親文字として扱う漢字
+fn is_kanji(c: char) -> bool {
+    ('\u{4E00}'..='\u{9FFF}').contains(&c)
+        || ('\u{3400}'..='\u{4DBF}').contains(&c)
+        || "々〆ヶ".contains(c)
+}
+
+// テキストの装飾を解析する
+// ルビ: ｜漢字《かんじ》、または漢字《かんじ》(直前の漢字が親文字)
+// 文字色: <color=#E53935>テキスト</color>
+pub fn parse_markup(text: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = vec![];
+    let mut colors: Vec<String> = vec![];
+    // 装飾のない文字を溜めておく
+    let mut buffer = String::new();
+    // ｜で始まったルビの親文字
+    let mut ruby_base: Option<String> = None;
+
+    fn flush(spans: &mut Vec<Span>, buffer: &mut String, color: Option<&String>) {
+        if !buffer.is_empty() {
+            spans.push(Span {
+                text: std::mem::take(buffer),
+                ruby: None,
+                color: color.cloned(),
+            });
+        }
+    }
+
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        // 閉じタグのない<color>、開きタグのない</color>はそのまま表示する
+        if let Some(tail) = rest.strip_prefix("<color=")
+            && let Some((color, tail)) = tail.split_once('>')
+            && tail.contains("</color>")
+        {
+            flush(&mut spans, &mut buffer, colors.last());
+            colors.push(video_option::normalize_color(color));
+            rest = tail;
+            continue;
+        }
+        if let Some(tail) = rest.strip_prefix("</color>")
+            && !colors.is_empty()
+        {
+            flush(&mut spans, &mut buffer, colors.last());
+            colors.pop();
+            rest = tail;
+            continue;
+        }
+
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '｜' => {
+                // 前の｜が閉じられていない場合はそのまま表示する
+                if let Some(base) = ruby_base.take() {
+                    buffer.push('｜');
+                    buffer.push_str(&base);
+                }
+                flush(&mut spans, &mut buffer, colors.last());
+                ruby_base = Some(String::new());
+            }
+            '《' if rest.contains('》') => {
+                let (ruby, tail) = rest.split_once('》').unwrap_or_default();
+                rest = tail;
+                let (base, bar) = match ruby_base.take() {
+                    Some(base) => (base, "｜"),
+                    None => {
+                        // 直前の連続する漢字を親文字とする
+                        let position = buffer
+                            .char_indices()
+                            .rev()
+                            .take_while(|(_, c)| is_kanji(*c))
+                            .last()
+                            .map(|(i, _)| i)
+                            .unwrap_or(buffer.len());
+                        (buffer.split_off(position), "")
+                    }
+                };
+                if base.is_empty() {
+                    // 親文字のない《》はそのまま表示する
+                    buffer.push_str(&format!("{}《{}》", bar, ruby));
+                } else {
+                    flush(&mut spans, &mut buffer, colors.last());
+                    spans.push(Span {
+                        text: base,
+                        ruby: Some(ruby.to_string()),
+                        color: colors.last().cloned(),
+                    });
+                }
+            }
+            c => match ruby_base.as_mut() {
+                Some(base) => base.push(c),
+                None => buffer.push(c),
+            },
+        }
+    }
+    // 閉じられていない｜はそのまま表示する
+    if let Some(base) = ruby_base {
+        buffer.push('｜');
+        buffer.push_str(&base);
+    }
+    flush(&mut spans, &mut buffer, colors.last());
+
+    spans
+}
+
+// 装飾を解釈する場合のみ解析し、解釈しない場合はテキスト全体を一つの部分とする
+pub fn spans(text: &str, markup: bool) -> Vec<Span> {
+    if markup {
+        return parse_markup(text);
+    }
+    vec![Span {
+        text: text.to_string(),
+        ruby: None,
+        color: None,
+    }]
+}
+
+// 装飾を取り除いた表示用のテキスト
+pub fn plain_text(text: &str, markup: bool) -> String {
+    spans(text, markup)
+        .into_iter()
+        .map(|span| span.text)
+        .collect()
+}
+
+// 読み上げ用のテキスト(ルビのある箇所はルビを読む)
+pub fn reading_text(text: &str, markup: bool) -> String {
+    spans(text, markup)
+        .into_iter()
+        .map(|span| span.ruby.unwrap_or(span.text))
+        .collect()
+}
+
+// 表示幅の概算(全角を1、半角を0.5とする)
+// libassは文字の位置を返さないため、ルビの位置はこの幅から求める
+fn text_width(text: &str) -> f64 {
+    text.chars()
+        .map(|c| if c.is_ascii() { 0.5 } else { 1.0 })
+        .sum()
+}
+
+// ルビを親文字の上に配置するイベント
+// 字幕は1行で表示される前提で、位置を概算する
+fn ruby_events(op: &Op, spans: &[Span], label: &str, start: f64, end: f64) -> Vec<Event> {
+    let font_size = op
+        .font_size
+        .as_deref()
+        .and_then(|size| size.parse::<f64>().ok())
+        .unwrap_or(36.0);
+    let width = op.w.unwrap_or(1920) as f64;
+    let height = op.h.unwrap_or(1080) as f64;
+    let margin_x = op.margin_x.unwrap_or(0) as f64;
+    let margin_y = op.margin_y.unwrap_or(50) as f64;
+
+    let line_width = (text_width(label)
+        + spans.iter().map(|span| text_width(&span.text)).sum::<f64>())
+        * font_size;
+    let mut x = match op.caption_align.unwrap_or(CaptionAlign::Center) {
+        CaptionAlign::Left => margin_x,
+        CaptionAlign::Center => (width - line_width) / 2.0,
+        CaptionAlign::Right => width - margin_x - line_width,
+    } + text_width(label) * font_size;
+    // 親文字の上端
+    let y = match op.caption_position.unwrap_or(CaptionPosition::Bottom) {
+        CaptionPosition::Bottom => height - margin_y - font_size,
+        CaptionPosition::Top => margin_y,
+        CaptionPosition::Center => (height - font_size) / 2.0,
+        // 座標がffmpegの式のため、位置を求められない
+        CaptionPosition::Custom => return vec![],
+    };
+    let ruby_size = (font_size / 2.0).round();
+
+    let mut events = vec![];
+    for span in spans {
+        let span_width = text_width(&span.text) * font_size;
+        if let Some(ruby) = span.ruby.as_deref() {
+            let color = span.color.as_deref().map(color_tag).unwrap_or_default();
+            events.push(Event {
+                start,
+                end,
+                style: "Default".to_string(),
+                text: format!(
+                    "{{\\an2\\pos({},{})\\fs{}{}}}{}",
+                    (x + span_width / 2.0).round(),
+                    y.round(),
+                    ruby_size,
+                    color,
+                    escape_text(ruby)
+                ),
+            });
+        }
+        x += span_width;
+    }
+
+    events
+}
+
+// 動画生成オプションから字幕を作る
+// timings: カラオケ表示にする場合のモーラのタイミング
+// start: 動画内での字幕の開始位置(秒)、duration: 表示する長さ(秒)
+pub fn caption_document(
+    op: &Op,
+    timings: Option<&[MoraTiming]>,
+    start: f64,
+    duration: f64,
+) -> Document {
+    let mut document = Document::new(op.w.unwrap_or(1920), op.h.unwrap_or(1080));
+    document.styles.push(Style::from_op("Default", op));
+
+    let spans = spans(op.word.as_deref().unwrap_or(""), op.uses_markup());
+    let label = op
+        .label
+        .as_deref()
+        .map(|label| format!("{}：", label))
+        .unwrap_or_default();
+    let text = match timings {
+        // 話者名は読み上げないため、最初から塗った状態にする
+        Some(timings) => {
+            let plain = spans
+                .iter()
+                .map(|span| span.text.as_str())
+                .collect::<String>();
+            let label = if label.is_empty() {
+                label.clone()
+            } else {
+                format!("{{\\k0}}{}", escape_text(&label))
+            };
+            format!("{}{}", label, karaoke_text(&plain, timings))
+        }
+        None => {
+            let mut text = escape_text(&label);
+            for span in spans.iter() {
+                match span.color.as_deref() {
+                    Some(color) => text.push_str(&format!(
+                        "{{{}}}{}{{\\r}}",
+                        color_tag(color),
+                        escape_text(&span.text)
+                    )),
+                    None => text.push_str(&escape_text(&span.text)),
+                }
+            }
+            text
+        }
+    };
+    document.events.push(Event {
+        start,
+        end: start + duration,
+        style: "Default".to_string(),
+        text,
+    });
+    document
+        .events
+        .extend(ruby_events(op, &spans, &label, start, start + duration));
+
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(start: f64, end: f64) -> MoraTiming {
+        MoraTiming {
+            text: "".to_string(),
+            vowel: "a".to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn test_ass_color_and_time() {
+        assert_eq!(ass_color("white"), "&H00FFFFFF");
+        assert_eq!(ass_color("0xBBDEFB"), "&H00FBDEBB");
+        assert_eq!(ass_color("black@0.5"), "&H80000000");
+        assert_eq!(ass_time(3723.456), "1:02:03.46");
+    }
+
+    #[test]
+    fn test_karaoke_text() {
+        // 4モーラ(ア・カ・イ・ネ)に対して2文字(赤い)+句読点
+        let timings = vec![
+            timing(0.1, 0.2),
+            timing(0.2, 0.3),
+            timing(0.3, 0.4),
+            timing(0.4, 0.6),
+        ];
+        assert_eq!(
+            karaoke_text("赤い。", &timings),
+            "{\\k10}{\\kf20}赤{\\kf30}い。"
+        );
+
+        // 長音も1モーラとして塗る(ラ・ー・メ・ン)
+        assert_eq!(
+            karaoke_text("ラーメン！", &timings),
+            "{\\k10}{\\kf10}ラ{\\kf10}ー{\\kf10}メ{\\kf20}ン！"
+        );
+
+        assert_eq!(karaoke_text("{x}", &[]), "｛x｝");
+    }
+
+    #[test]
+    fn test_parse_markup() {
+        let spans = parse_markup("｜明日《あした》は<color=#E53935>晴天《せいてん》です</color>。");
+        assert_eq!(
+            spans,
+            vec![
+                Span {
+                    text: "明日".to_string(),
+                    ruby: Some("あした".to_string()),
+                    color: None,
+                },
+                Span {
+                    text: "は".to_string(),
+                    ruby: None,
+                    color: None,
+                },
+                Span {
+                    text: "晴天".to_string(),
+                    ruby: Some("せいてん".to_string()),
+                    color: Some("0xE53935".to_string()),
+                },
+                Span {
+                    text: "です".to_string(),
+                    ruby: None,
+                    color: Some("0xE53935".to_string()),
+                },
+                Span {
+                    text: "。".to_string(),
+                    ruby: None,
+                    color: None,
+                },
+            ]
+        );
+
+        let text = "｜明日《あした》は<color=red>晴天</color>";
+        assert_eq!(plain_text(text, true), "明日は晴天");
+        assert_eq!(reading_text(text, true), "あしたは晴天");
+        // 装飾を解釈しない場合はそのまま
+        assert_eq!(plain_text(text, false), text);
+        assert_eq!(reading_text(text, false), text);
+
+        // 親文字のない《》、対応しない記号はそのまま表示する
+        for text in [
+            "《注意》してください",
+            "かな《かな》",
+            "｜《そのまま》",
+            "｜閉じない",
+            "<color=red>閉じない",
+            "開かない</color>",
+            "《閉じない",
+        ] {
+            assert_eq!(plain_text(text, true), text);
+        }
+    }
+
+    #[test]
+    fn test_caption_document() {
+        let mut op = Op {
+            markup: Some(true),
+            ..Default::default()
+        };
+        op.set_word("<color=red>漢字《かんじ》</color>");
+        let ass = caption_document(&op, None, 1.0, 2.0).to_ass();
+
+        assert!(ass.contains("PlayResX: 1920"));
+        assert!(ass.contains("Style: Default,Noto Sans JP,36,&H00FFFFFF,&H00FFFFFF,"));
+        assert!(
+            ass.contains(
+                "Dialogue: 0,0:00:01.00,0:00:03.00,Default,,0,0,0,,{\\c&H0000FF&}漢字{\\r}"
+            )
+        );
+        // 1920の中央に幅72の親文字、上端は1080-50-36
+        assert!(ass.contains("{\\an2\\pos(960,994)\\fs18\\c&H0000FF&}かんじ"));
+    }
+}
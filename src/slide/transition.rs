@@ -0,0 +1,192 @@
+use log::warn;
+
+// セクション間のトランジション
+// ffmpegのxfade/acrossfadeフィルタで実現する
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    // 黒を経由してフェード
+    Fade,
+    // 前後の映像を重ねてフェード
+    Crossfade,
+    // ワイプ
+    Wipe,
+    // スライド
+    Slide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    pub kind: Kind,
+    // 秒
+    pub duration: f64,
+}
+
+const DEFAULT_DURATION: f64 = 0.5;
+
+impl Transition {
+    // "種類[:秒]"の形式を解析する
+    // 例) fade, crossfade:1.0, wipe:0.3
+    // "none"の場合はトランジションなし(ハードカット)
+    pub fn parse(value: &str) -> Result<Option<Transition>, String> {
+        let mut split = value.trim().splitn(2, ':');
+        let kind = match split.next().unwrap_or("").trim().to_lowercase().as_str() {
+            "" | "none" | "cut" => return Ok(None),
+            "fade" => Kind::Fade,
+            "crossfade" | "dissolve" => Kind::Crossfade,
+            "wipe" => Kind::Wipe,
+            "slide" => Kind::Slide,
+            other => return Err(format!("unknown transition: {}", other)),
+        };
+
+        let duration = match split.next() {
+            Some(duration) => duration
+                .trim()
+                .parse::<f64>()
+                .map_err(|e| format!("invalid transition duration: {}", e))?,
+            None => DEFAULT_DURATION,
+        };
+        if duration <= 0.0 {
+            return Err(format!("invalid transition duration: {}", duration));
+        }
+
+        Ok(Some(Transition { kind, duration }))
+    }
+
+    // 全体に適用するトランジション
+    pub fn from_env() -> Option<Transition> {
+        let value = std::env::var("DEFAULT_TRANSITION").unwrap_or_default();
+        Transition::parse(&value).unwrap_or_else(|e| {
+            warn!("DEFAULT_TRANSITION ignored: {}", e);
+            None
+        })
+    }
+
+    // xfadeのtransition名
+    fn xfade_name(&self) -> &'static str {
+        match self.kind {
+            Kind::Fade => "fadeblack",
+            Kind::Crossfade => "fade",
+            Kind::Wipe => "wipeleft",
+            Kind::Slide => "slideleft",
+        }
+    }
+}
+
+// 動画群をトランジション付きで連結するためのフィルタを生成する
+// durations: 各入力動画の長さ(秒)
+// transitions: 各境界のトランジション(durations.len() - 1 個)。Noneの境界はハードカット
+// 出力ラベルは[vout][aout]
+pub fn create_filter_complex(durations: &[f64], transitions: &[Option<Transition>]) -> String {
+    let mut args = vec![];
+
+    // xfadeは入力のタイムベースが一致している必要がある
+    for i in 0..durations.len() {
+        args.push(format!("[{i}:v]settb=AVTB,setpts=PTS-STARTPTS[v{i}in];"));
+    }
+
+    let mut prev_v = "[v0in]".to_string();
+    let mut prev_a = "[0:a]".to_string();
+    let mut elapsed = durations.first().copied().unwrap_or_default();
+
+    for (i, duration) in durations.iter().enumerate().skip(1) {
+        let (v, a) = if i == durations.len() - 1 {
+            ("[vout]".to_string(), "[aout]".to_string())
+        } else {
+            (format!("[v{i}]"), format!("[a{i}]"))
+        };
+
+        match transitions.get(i - 1).copied().flatten() {
+            Some(transition) => {
+                // 前後の動画より長いトランジションはできない
+                let d = transition.duration.min(durations[i - 1]).min(*duration);
+                let offset = elapsed - d;
+                args.push(format!(
+                    "{prev_v}[v{i}in]xfade=transition={}:duration={d}:offset={offset}{v};",
+                    transition.xfade_name()
+                ));
+                args.push(format!("{prev_a}[{i}:a]acrossfade=d={d}{a};"));
+                elapsed += duration - d;
+            }
+            None => {
+                args.push(format!(
+                    "{prev_v}{prev_a}[v{i}in][{i}:a]concat=n=2:v=1:a=1{v}{a};"
+                ));
+                elapsed += duration;
+            }
+        }
+
+        prev_v = v;
+        prev_a = a;
+    }
+
+    let mut filter = args.join("");
+    filter.pop(); // 末尾の;
+    filter
+}
+
+// 連結後の動画での各入力動画の開始時刻(秒)
+// トランジションのある境界では、トランジションの開始時刻とする
+// create_filter_complexと同じ計算をする
+pub fn start_times(durations: &[f64], transitions: &[Option<Transition>]) -> Vec<f64> {
+    let mut starts = vec![];
+    let mut elapsed = 0.0;
+    for (i, duration) in durations.iter().enumerate() {
+        let transition = match i {
+            0 => None,
+            _ => transitions.get(i - 1).copied().flatten(),
+        };
+        let d = match transition {
+            Some(transition) => transition.duration.min(durations[i - 1]).min(*duration),
+            None => 0.0,
+        };
+        starts.push(elapsed - d);
+        elapsed += duration - d;
+    }
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Transition::parse("none").unwrap(), None);
+        assert_eq!(
+            Transition::parse("crossfade:1.5").unwrap(),
+            Some(Transition {
+                kind: Kind::Crossfade,
+                duration: 1.5
+            })
+        );
+        assert_eq!(Transition::parse("wipe").unwrap().unwrap().duration, 0.5);
+        assert!(Transition::parse("spin").is_err());
+        assert!(Transition::parse("fade:-1").is_err());
+    }
+
+    #[test]
+    fn test_create_filter_complex() {
+        let fade = Transition::parse("fade:1").unwrap();
+        let filter = create_filter_complex(&[5.0, 4.0, 3.0], &[fade, None]);
+
+        assert!(filter.contains("[v0in][v1in]xfade=transition=fadeblack:duration=1:offset=4[v1]"));
+        assert!(filter.contains("[0:a][1:a]acrossfade=d=1[a1]"));
+        assert!(filter.contains("[v1][a1][v2in][2:a]concat=n=2:v=1:a=1[vout][aout]"));
+        assert!(!filter.ends_with(';'));
+
+        // 長い動画の後の短い動画では、短い方の長さまでに制限する
+        let filter = create_filter_complex(&[10.0, 0.5, 3.0], &[fade, fade]);
+        assert!(filter.contains("xfade=transition=fadeblack:duration=0.5:offset=9.5[v1]"));
+        assert!(filter.contains("xfade=transition=fadeblack:duration=0.5:offset=9.5[vout]"));
+
+        assert_eq!(
+            start_times(&[5.0, 4.0, 3.0], &[fade, None]),
+            vec![0.0, 4.0, 8.0]
+        );
+        assert_eq!(
+            start_times(&[10.0, 0.5, 3.0], &[fade, fade]),
+            vec![0.0, 9.5, 9.5]
+        );
+    }
+}
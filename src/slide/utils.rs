@@ -1,30 +1,197 @@
-use std::path::PathBuf;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
 
+use serde::de::DeserializeOwned;
+
+// 環境変数で指定したパスを絶対パスにする
+// 相対パスはカレントディレクトリを基準にする
 pub fn target_path_from_env(target: &str) -> PathBuf {
     let target = std::env::var(target).unwrap_or("./".to_string());
-    let target_path = PathBuf::from(target.clone());
-    if target_path.is_absolute() {
-        target_path
-    } else {
-        let current_dir = std::env::current_dir().unwrap();
-        // 相対パスのコンポーネントを処理
-        let mut result_path = current_dir;
-        for component in target_path.components() {
-            match component {
-                std::path::Component::CurDir => {
-                    // ./ は無視
-                    continue;
-                }
-                std::path::Component::ParentDir => {
-                    // ../ は親ディレクトリに移動
-                    result_path.pop();
+    let current_dir = std::env::current_dir().unwrap();
+    absolute_path(Path::new(&target), &current_dir)
+}
+
+// パスを絶対パスにする
+// 相対パスはbase_dirを基準にし、./ と ../ を取り除く(ファイルの存在は確認しない)
+pub fn absolute_path(path: &Path, base_dir: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return normalize(path);
+    }
+    normalize(&base_dir.join(path))
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut result_path = PathBuf::new();
+    for component in path.components() {
+        match component {
+            // ./ は無視
+            Component::CurDir => continue,
+            // ../ は親ディレクトリに移動
+            Component::ParentDir => {
+                result_path.pop();
+            }
+            // それ以外は追加
+            _ => result_path.push(component),
+        }
+    }
+    result_path
+}
+
+// ワイルドカード(* ?)を含むパスか
+pub fn is_glob(path: &str) -> bool {
+    path.contains('*') || path.contains('?')
+}
+
+// ワイルドカードに一致するファイルを、ファイル名の番号順(slide2 < slide10)に返す
+pub fn expand_glob(pattern: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut paths = glob::glob(&pattern.to_string_lossy())
+        .map_err(|e| format!("invalid glob pattern {}: {}", pattern.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .collect::<Vec<PathBuf>>();
+    paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+
+    Ok(paths)
+}
+
+// 数字の並びを数値として比較する
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                match x.cmp(&y) {
+                    Ordering::Equal => continue,
+                    other => return other,
                 }
-                _ => {
-                    // それ以外は追加
-                    result_path.push(component);
+            }
+            (Some(x), Some(y)) => {
+                a.next();
+                b.next();
+                match x.cmp(&y) {
+                    Ordering::Equal => continue,
+                    other => return other,
                 }
             }
         }
-        result_path
+    }
+}
+
+// ボイスIDをキーとするJSONファイルを読み込む
+// 例) { "2": { ... }, "3": { ... } }
+// kind: エラー表示用の種類(speakers、charactersなど)
+pub fn read_voice_map<T: DeserializeOwned>(
+    path: &str,
+    kind: &str,
+) -> Result<HashMap<i32, T>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {} {}: {}", kind, path, e))?;
+    parse_voice_map(&text).map_err(|e| format!("failed to parse {} {}: {}", kind, path, e))
+}
+
+pub fn parse_voice_map<T: DeserializeOwned>(text: &str) -> Result<HashMap<i32, T>, String> {
+    serde_json::from_str::<HashMap<String, T>>(text)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|(voice_id, value)| {
+            voice_id
+                .trim()
+                .parse::<i32>()
+                .map(|voice_id| (voice_id, value))
+                .map_err(|e| format!("invalid voice id {}: {}", voice_id, e))
+        })
+        .collect()
+}
+
+// 属性の並びを , で分割する
+// 括弧と引用符の中の , は分割しない(ffmpegの式 if(gt(t,1),1,0) など)
+pub fn split_attributes(text: &str) -> Vec<&str> {
+    let mut attributes = vec![];
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => {
+                attributes.push(&text[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    attributes.push(&text[start..]);
+    attributes
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> u64 {
+    let mut number = 0u64;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        number = number.saturating_mul(10).saturating_add(digit as u64);
+        chars.next();
+    }
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_absolute_path_and_glob() {
+        let base_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        assert_eq!(
+            absolute_path(Path::new("./slides/../slides/1.png"), &base_dir),
+            base_dir.join("slides").join("1.png")
+        );
+        assert_eq!(
+            absolute_path(Path::new("/tmp/./a.png"), &base_dir),
+            PathBuf::from("/tmp/a.png")
+        );
+
+        let slides_dir = base_dir.join("slides");
+        std::fs::create_dir_all(&slides_dir).unwrap();
+        for name in ["slide10.png", "slide2.png", "slide1.png", "notes.txt"] {
+            std::fs::write(slides_dir.join(name), "").unwrap();
+        }
+        let paths = expand_glob(&slides_dir.join("*.png")).unwrap();
+        std::fs::remove_dir_all(&base_dir).unwrap();
+
+        assert_eq!(
+            paths,
+            vec![
+                slides_dir.join("slide1.png"),
+                slides_dir.join("slide2.png"),
+                slides_dir.join("slide10.png"),
+            ]
+        );
+        assert!(is_glob("slides/*.png"));
+        assert!(!is_glob(r"C:\slides\1.png"));
+    }
+
+    #[test]
+    fn test_split_attributes() {
+        assert_eq!(
+            split_attributes("x=if(gt(t,1),10,20), font_color=black"),
+            vec!["x=if(gt(t,1),10,20)", " font_color=black"]
+        );
+        assert_eq!(
+            split_attributes("title_text='a, b',@2"),
+            vec!["title_text='a, b'", "@2"]
+        );
+        assert_eq!(split_attributes(""), vec![""]);
     }
 }
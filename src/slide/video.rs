@@ -1,8 +1,42 @@
 use std::io::Write;
 
-use log::info;
+use log::{info, warn};
 
-use crate::slide::{utils, video_option::Op, voice};
+use crate::slide::{probe, transition, utils, video_option::Op, voice};
+
+// セクションの動画生成方法
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    // テキスト(音声)ごとに動画を生成し、連結する
+    Part,
+    // セクションを一つのffmpegジョブで生成する
+    Section,
+}
+
+impl RenderMode {
+    pub fn from_env() -> RenderMode {
+        match std::env::var("DEFAULT_SECTION_RENDER_MODE")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "section" => RenderMode::Section,
+            _ => RenderMode::Part,
+        }
+    }
+}
+
+fn output_filename(key: &str) -> String {
+    let output_dir = utils::target_path_from_env("DEFAULT_OUTPUT_VIDEO_FILE_DIR");
+
+    let mut row = output_dir.join(format!("{}.mp4", key));
+    if row.is_relative() {
+        // ./
+        row = std::env::current_dir().unwrap().join(row);
+    }
+
+    row.to_string_lossy().into_owned()
+}
 
 pub fn create_args(
     key: String,
@@ -19,53 +53,170 @@ pub fn create_args(
 
     let duration = input_voice.duration.num_milliseconds() as f64 / 1000.0;
 
-    let output_filename = {
-        let output_dir = utils::target_path_from_env("DEFAULT_OUTPUT_VIDEO_FILE_DIR");
+    let output_filename = output_filename(&key);
 
-        let mut row = output_dir.join(format!("{}.mp4", key));
-        if row.is_relative() {
-            // ./
-            row = std::env::current_dir().unwrap().join(row);
+    // キャラクターを表示する場合は、画像を入力に追加
+    let mut character_inputs = vec![];
+    if let Some(character) = op.character.as_ref() {
+        for input in character.inputs() {
+            character_inputs.extend([
+                "-loop".to_string(),
+                "1".to_string(),
+                "-i".to_string(),
+                input,
+            ]);
         }
+    }
+
+    let mut args = vec![
+        // 画像を繰り返しフレームに表示する
+        "-loop".to_string(), // 画像をループ再生するオプション
+        "1".to_string(),     // ループ回数(1で無限ループ)
+        // インプット画像または動画ファイル
+        "-i".to_string(), // 画像または動画ファイルを入力として指定
+        input_filepath,   // 入力ファイルパス
+        // インプット音声ファイル
+        "-i".to_string(),     // 音声ファイルを入力として指定
+        input_voice.filepath, // 音声ファイルパス
+    ];
+    // キャラクターの画像(口閉じ、口開き、まばたき)
+    args.extend(character_inputs);
+    args.extend([
+        // フィルターを追加
+        "-filter_complex".to_string(),   // 複雑なフィルタ構成を指定
+        filter,                          // フィルタ内容
+        "-map".to_string(),              // 映像ストリームのマッピングを指定
+        "[out2]".to_string(),            // 映像出力ラベル
+        "-map".to_string(),              // 音声ストリームのマッピングを指定
+        "1:a".to_string(),               // 二番目の入力ファイルの音声を使用
+        "-s".to_string(),                // 出力動画の解像度を指定
+        format!("{}x{}", width, height), // 横×縦のフォーマット
+        "-t".to_string(),                // 出力の長さを指定
+        format!("{}", duration),         // 音声の長さに合わせた秒数
+        "-c:v".to_string(),              // ビデオコーデックの指定
+        "hevc_nvenc".to_string(),        // NVIDIAのHEVCハードウェアエンコード
+        "-c:a".to_string(),              // オーディオコーデックを指定
+        "aac".to_string(),               // AACを用いた音声エンコード
+        "-pix_fmt".to_string(),          // ピクセルフォーマットの指定
+        "yuv420p".to_string(),           // yuv420p形式
+        // "-shortest".to_string(),         // 入力の中で最も短いストリームに合わせて終了
+        // 出力ファイル名はkeyから決まるため、再実行時は前回の出力を上書きする
+        "-y".to_string(),        // 出力ファイルを上書き
+        output_filename.clone(), // 出力ファイル名
+    ]);
+
+    (args, output_filename)
+}
 
-        row.to_string_lossy().into_owned()
+// セクション全体を一つのffmpegジョブで生成するためのパラメータ
+// 音声を連結し、字幕はenable='gte(t,a)*lt(t,b)'で切り替える(境界で重ならないよう終わりは含まない)
+// 画像のデコードが一度で済み、パートの境界での途切れも発生しない
+// parts: テキストごとの音声と字幕オプション。背景やサイズは先頭のオプションを使う
+pub fn create_section_args(
+    key: String,
+    input_filepath: String,
+    parts: Vec<(voice::Data, Op)>,
+) -> (Vec<String>, String) {
+    let output_filename = output_filename(&key);
+    let (width, height) = match parts.first() {
+        Some((_, op)) => (op.w.unwrap_or_default(), op.h.unwrap_or_default()),
+        None => (0, 0),
     };
 
-    (
-        vec![
-            // 画像を繰り返しフレームに表示する
-            "-loop".to_string(), // 画像をループ再生するオプション
-            "1".to_string(),     // ループ回数(1で無限ループ)
-            // インプット画像または動画ファイル
-            "-i".to_string(), // 画像または動画ファイルを入力として指定
-            input_filepath,   // 入力ファイルパス
-            // インプット音声ファイル
-            "-i".to_string(),     // 音声ファイルを入力として指定
-            input_voice.filepath, // 音声ファイルパス
-            // キャラクターなどを出力する場合あここに[-i, input_filepath]を追加
-            // フィルターを追加
-            "-filter_complex".to_string(),   // 複雑なフィルタ構成を指定
-            filter,                          // フィルタ内容
-            "-map".to_string(),              // 映像ストリームのマッピングを指定
-            "[out2]".to_string(),            // 映像出力ラベル
-            "-map".to_string(),              // 音声ストリームのマッピングを指定
-            "1:a".to_string(),               // 二番目の入力ファイルの音声を使用
-            "-s".to_string(),                // 出力動画の解像度を指定
-            format!("{}x{}", width, height), // 横×縦のフォーマット
-            "-t".to_string(),                // 出力の長さを指定
-            format!("{}", duration),         // 音声の長さに合わせた秒数
-            "-c:v".to_string(),              // ビデオコーデックの指定
-            "hevc_nvenc".to_string(),        // NVIDIAのHEVCハードウェアエンコード
-            "-c:a".to_string(),              // オーディオコーデックを指定
-            "aac".to_string(),               // AACを用いた音声エンコード
-            "-pix_fmt".to_string(),          // ピクセルフォーマットの指定
-            "yuv420p".to_string(),           // yuv420p形式
-            // "-shortest".to_string(),         // 入力の中で最も短いストリームに合わせて終了
-            // "-y".to_string(),                // 出力ファイルを上書き
-            output_filename.clone(), // 出力ファイル名
-        ],
-        output_filename,
-    )
+    let mut args = vec![
+        "-loop".to_string(),
+        "1".to_string(),
+        "-i".to_string(),
+        input_filepath,
+    ];
+    for (voice_data, _) in parts.iter() {
+        args.push("-i".to_string());
+        args.push(voice_data.filepath.clone());
+    }
+
+    // 映像: 背景にキャラクターと字幕を時間帯ごとに重ねる
+    let mut filters = vec![];
+    if let Some((_, op)) = parts.first() {
+        filters.push(op.create_base_filter());
+    }
+    let mut elapsed = 0.0;
+    let mut input_label = "[bg]".to_string();
+    // キャラクターの画像は音声の後の入力とする
+    let mut next_input = parts.len() + 1;
+    for (i, (voice_data, op)) in parts.iter().enumerate() {
+        let duration = voice_data.duration.num_milliseconds() as f64 / 1000.0;
+        let Some(character) = op.character.as_ref() else {
+            elapsed += duration;
+            continue;
+        };
+        let output_label = format!("[chr{}]", i);
+        filters.push(character.create_filter(
+            &input_label,
+            &output_label,
+            next_input,
+            elapsed,
+            Some((elapsed, elapsed + duration)),
+        ));
+        filters.push(";".to_string());
+        for input in character.inputs() {
+            args.extend([
+                "-loop".to_string(),
+                "1".to_string(),
+                "-i".to_string(),
+                input,
+            ]);
+            next_input += 1;
+        }
+        elapsed += duration;
+        input_label = output_label;
+    }
+
+    elapsed = 0.0;
+    for (i, (voice_data, op)) in parts.iter().enumerate() {
+        let duration = voice_data.duration.num_milliseconds() as f64 / 1000.0;
+        let output_label = if i == parts.len() - 1 {
+            "[out2]".to_string()
+        } else {
+            format!("[c{}]", i)
+        };
+        filters.push(op.create_caption_filter(
+            &input_label,
+            &output_label,
+            Some((elapsed, elapsed + duration)),
+        ));
+        filters.push(";".to_string());
+        elapsed += duration;
+        input_label = output_label;
+    }
+
+    // 音声: 全ての音声を連結する
+    for i in 0..parts.len() {
+        filters.push(format!("[{}:a]", i + 1));
+    }
+    filters.push(format!("concat=n={}:v=0:a=1[aout]", parts.len()));
+
+    args.extend([
+        "-filter_complex".to_string(),
+        filters.join(""),
+        "-map".to_string(),
+        "[out2]".to_string(),
+        "-map".to_string(),
+        "[aout]".to_string(),
+        "-s".to_string(),
+        format!("{}x{}", width, height),
+        "-t".to_string(),
+        format!("{}", elapsed),
+        "-c:v".to_string(),
+        "hevc_nvenc".to_string(),
+        "-c:a".to_string(),
+        "aac".to_string(),
+        "-pix_fmt".to_string(),
+        "yuv420p".to_string(),
+        "-y".to_string(),
+        output_filename.clone(),
+    ]);
+
+    (args, output_filename)
 }
 
 // create video
@@ -110,7 +261,50 @@ pub fn create_output_files(video_files: Vec<String>) -> (String, String) {
     )
 }
 
+// 連結用ファイルから動画ファイルのパスを読み込む
+// file 'path' の形式
+fn read_concat_file(concat_file: &str) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(concat_file).map_err(|e| e.to_string())?;
+    Ok(text
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim().strip_prefix("file ")?;
+            Some(line.trim().trim_matches('\'').to_string())
+        })
+        .collect())
+}
+
 pub async fn concat(concat_file: String, output_file: String) -> Result<String, String> {
+    // -c copyでの連結は全ての動画のコーデックなどが一致している必要がある
+    // 一致しない場合は再エンコードして連結する
+    let video_files = read_concat_file(&concat_file)?;
+    let mut params = vec![];
+    for video_file in video_files.iter() {
+        params.push(probe::stream_params(video_file).await?);
+    }
+    if let Some(reference) = params.first() {
+        let mismatches = video_files
+            .iter()
+            .zip(params.iter())
+            .filter_map(|(video_file, param)| {
+                let differences = reference.differences(param);
+                if differences.is_empty() {
+                    None
+                } else {
+                    Some(format!("{} ({})", video_file, differences.join(", ")))
+                }
+            })
+            .collect::<Vec<String>>();
+
+        if !mismatches.is_empty() {
+            warn!(
+                "stream parameters mismatch, re-encoding concat: {:?}",
+                mismatches
+            );
+            return concat_reencode(video_files, reference, output_file).await;
+        }
+    }
+
     // ffmpegのパラメータ引数
     let result = {
         let args = [
@@ -141,6 +335,116 @@ pub async fn concat(concat_file: String, output_file: String) -> Result<String,
     }
 }
 
+// concatフィルタで再エンコードして連結する
+// 先頭の動画のパラメータに合わせる
+async fn concat_reencode(
+    video_files: Vec<String>,
+    reference: &probe::StreamParams,
+    output_file: String,
+) -> Result<String, String> {
+    let width = reference.width.unwrap_or(1920);
+    let height = reference.height.unwrap_or(1080);
+    let frame_rate = reference.frame_rate.as_deref().unwrap_or("25");
+    let sample_rate = reference.sample_rate.as_deref().unwrap_or("48000");
+    let channel_layout = reference.channel_layout.as_deref().unwrap_or("mono");
+
+    let mut args = vec![];
+    let mut filters = vec![];
+    for (i, video_file) in video_files.iter().enumerate() {
+        args.push("-i".to_string());
+        args.push(video_file.clone());
+        filters.push(format!(
+            "[{i}:v]scale={width}:{height}:force_original_aspect_ratio=decrease,\
+            pad={width}:{height}:({width}-iw)/2:({height}-ih)/2,\
+            setsar=1,fps={frame_rate},format=yuv420p[v{i}];\
+            [{i}:a]aformat=sample_rates={sample_rate}:channel_layouts={channel_layout}[a{i}];"
+        ));
+    }
+    for i in 0..video_files.len() {
+        filters.push(format!("[v{i}][a{i}]"));
+    }
+    filters.push(format!(
+        "concat=n={}:v=1:a=1[vout][aout]",
+        video_files.len()
+    ));
+
+    args.extend([
+        "-filter_complex".to_string(),
+        filters.join(""),
+        "-map".to_string(),
+        "[vout]".to_string(),
+        "-map".to_string(),
+        "[aout]".to_string(),
+        "-c:v".to_string(),
+        "hevc_nvenc".to_string(),
+        "-c:a".to_string(),
+        "aac".to_string(),
+        "-pix_fmt".to_string(),
+        "yuv420p".to_string(),
+        output_file.clone(),
+    ]);
+
+    let result = std::process::Command::new("ffmpeg")
+        .args(args)
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    info!("result concat video with re-encode: {:?}", result);
+
+    if result.status.success() {
+        Ok(output_file)
+    } else {
+        Err(format!("failed concat re-encode: {:?}", result))
+    }
+}
+
+// トランジション付きで動画を連結する
+// concat demuxerではなくフィルタで連結するため、再エンコードが発生する
+// durations: 各動画の長さ(秒)
+// transitions: 各境界のトランジション(video_files.len() - 1 個)
+pub async fn concat_with_transitions(
+    video_files: Vec<String>,
+    durations: Vec<f64>,
+    transitions: Vec<Option<transition::Transition>>,
+    output_file: String,
+) -> Result<String, String> {
+    let filter = transition::create_filter_complex(&durations, &transitions);
+
+    let mut args = vec![];
+    for video_file in video_files {
+        args.push("-i".to_string());
+        args.push(video_file);
+    }
+    args.extend([
+        "-filter_complex".to_string(),
+        filter,
+        "-map".to_string(),
+        "[vout]".to_string(),
+        "-map".to_string(),
+        "[aout]".to_string(),
+        "-c:v".to_string(),
+        "hevc_nvenc".to_string(),
+        "-c:a".to_string(),
+        "aac".to_string(),
+        "-pix_fmt".to_string(),
+        "yuv420p".to_string(),
+        output_file.clone(),
+    ]);
+
+    let result = std::process::Command::new("ffmpeg")
+        .args(args)
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    info!("result concat video with transitions: {:?}", result);
+
+    if result.status.success() {
+        Ok(output_file)
+    } else {
+        Err(format!("failed concat with transitions: {:?}", result))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::TimeDelta;
@@ -156,6 +460,8 @@ mod tests {
             voice_id: Some(14),
             filepath: "test.wav".to_string(),
             duration: TimeDelta::seconds(10),
+            loudness: None,
+            query_filepath: None,
         };
 
         let op = Op {
@@ -168,14 +474,66 @@ mod tests {
             font_color: Some("white".to_string()),
             border_color: Some("0xBBDEFB".to_string()),
             word: Some("".to_string()),
+            ..Default::default()
         };
 
         let (args, output_filename) = create_args(key, input_filepath, input_voice, Some(op));
 
-        assert_eq!(args.len(), 24);
+        assert_eq!(args.len(), 25);
         assert_eq!(output_filename, r"output\test.mp4");
     }
 
+    #[test]
+    fn test_create_section_args() {
+        let parts = ["はじめ", "つぎ"]
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                let voice_data = voice::Data {
+                    voice_id: Some(2),
+                    filepath: format!("{}.wav", i),
+                    duration: TimeDelta::seconds(2),
+                    loudness: None,
+                    query_filepath: None,
+                };
+                let mut op = Op::default();
+                op.set_word(word);
+                (voice_data, op)
+            })
+            .collect::<Vec<_>>();
+
+        let (args, _) = create_section_args("section".to_string(), "1.png".to_string(), parts);
+        let filter = &args[args.iter().position(|a| a == "-filter_complex").unwrap() + 1];
+
+        assert!(filter.contains("text='はじめ'"));
+        assert!(filter.contains("enable='gte(t,0)*lt(t,2)'[c0];[c0]drawtext"));
+        assert!(filter.contains("enable='gte(t,2)*lt(t,4)'[out2]"));
+        assert!(filter.ends_with("[1:a][2:a]concat=n=2:v=0:a=1[aout]"));
+        assert_eq!(args[args.iter().position(|a| a == "-t").unwrap() + 1], "4");
+    }
+
+    #[test]
+    fn test_same_key_overwrites() {
+        // 同じkeyで二回生成しても、前回の出力を上書きして失敗しない
+        let outputs = (0..2)
+            .map(|_| {
+                let voice_data = voice::Data::new(Some(2), "0.wav");
+                let mut op = Op::default();
+                op.set_word("はじめ");
+                let (args, output_filename) = create_section_args(
+                    "section".to_string(),
+                    "1.png".to_string(),
+                    vec![(voice_data, op)],
+                );
+                assert_eq!(args[args.len() - 2], "-y");
+                assert_eq!(args.last(), Some(&output_filename));
+                output_filename
+            })
+            .collect::<Vec<String>>();
+
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
     #[tokio::test]
     async fn test_concat() {
         let current_dir = std::env::current_dir().unwrap();
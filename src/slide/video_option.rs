@@ -1,13 +1,311 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::slide::{character, subtitle};
+
+// 静止画スライドに付与する動き(Ken Burns)
+// None: 動きなし。DEFAULT_MOTIONやテーマの動きをスライドごとに止める
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Motion {
+    None,
+    ZoomIn,
+    ZoomOut,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+}
+
+impl Motion {
+    pub fn parse(value: &str) -> Result<Option<Motion>, String> {
+        match value.trim().to_lowercase().replace('-', "_").as_str() {
+            "" => Ok(None),
+            "none" => Ok(Some(Motion::None)),
+            "zoom_in" => Ok(Some(Motion::ZoomIn)),
+            "zoom_out" => Ok(Some(Motion::ZoomOut)),
+            "pan_left" => Ok(Some(Motion::PanLeft)),
+            "pan_right" => Ok(Some(Motion::PanRight)),
+            "pan_up" => Ok(Some(Motion::PanUp)),
+            "pan_down" => Ok(Some(Motion::PanDown)),
+            other => Err(format!("unknown motion: {}", other)),
+        }
+    }
+}
+
+// 字幕の位置
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionPosition {
+    Bottom,
+    Top,
+    Center,
+    // caption_x/caption_yで指定
+    Custom,
+}
+
+impl CaptionPosition {
+    pub fn parse(value: &str) -> Result<CaptionPosition, String> {
+        match value.trim().to_lowercase().as_str() {
+            "bottom" => Ok(CaptionPosition::Bottom),
+            "top" => Ok(CaptionPosition::Top),
+            "center" | "middle" => Ok(CaptionPosition::Center),
+            "custom" => Ok(CaptionPosition::Custom),
+            other => Err(format!("unknown caption position: {}", other)),
+        }
+    }
+}
+
+// 字幕の横揃え
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl CaptionAlign {
+    pub fn parse(value: &str) -> Result<CaptionAlign, String> {
+        match value.trim().to_lowercase().as_str() {
+            "left" => Ok(CaptionAlign::Left),
+            "center" => Ok(CaptionAlign::Center),
+            "right" => Ok(CaptionAlign::Right),
+            other => Err(format!("unknown caption align: {}", other)),
+        }
+    }
+}
+
+// 字幕の表示方法
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionMode {
+    // テキスト全体を表示する
+    Static,
+    // 読み上げに合わせて文字を塗っていく(VOICEVOXのモーラの長さを使う)
+    Karaoke,
+}
+
+impl CaptionMode {
+    pub fn parse(value: &str) -> Result<CaptionMode, String> {
+        match value.trim().to_lowercase().as_str() {
+            "static" | "none" => Ok(CaptionMode::Static),
+            "karaoke" => Ok(CaptionMode::Karaoke),
+            other => Err(format!("unknown caption mode: {}", other)),
+        }
+    }
+}
+
+// 字幕の描画方法
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionRenderer {
+    // ffmpegのdrawtextフィルタ
+    Drawtext,
+    // ASS字幕を生成し、subtitlesフィルタ(libass)で焼き込む
+    // ルビや部分的な文字色を表示できる
+    Ass,
+}
+
+impl CaptionRenderer {
+    pub fn parse(value: &str) -> Result<CaptionRenderer, String> {
+        match value.trim().to_lowercase().as_str() {
+            "drawtext" => Ok(CaptionRenderer::Drawtext),
+            "ass" | "libass" => Ok(CaptionRenderer::Ass),
+            other => Err(format!("unknown caption renderer: {}", other)),
+        }
+    }
+}
+
+// スライドの縦横比が出力と異なる場合の余白の埋め方
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fill {
+    // 背景色(background_color)
+    Color,
+    // スライドを拡大してぼかしたもの
+    Blur,
+    // 画像ファイル(fill_image)
+    Image,
+    // background_colorからgradient_colorへのグラデーション
+    Gradient,
+}
+
+impl Fill {
+    pub fn parse(value: &str) -> Result<Fill, String> {
+        match value.trim().to_lowercase().as_str() {
+            "color" | "solid" | "pad" => Ok(Fill::Color),
+            "blur" => Ok(Fill::Blur),
+            "image" => Ok(Fill::Image),
+            "gradient" => Ok(Fill::Gradient),
+            other => Err(format!("unknown fill: {}", other)),
+        }
+    }
+}
+
+// スライドを出力サイズに合わせる方法
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScalePolicy {
+    // 縦横比を保って出力に収まるように拡大・縮小する
+    Fit,
+    // 縦横比を保って出力を覆うように拡大・縮小し、はみ出した部分を切り抜く
+    Fill,
+    // 縮小のみ行う(小さいスライドはそのままの大きさで中央に置く)
+    NoUpscale,
+    // 整数倍で拡大する(ドット絵やUIのスクリーンショット向け)
+    Integer,
+}
+
+impl ScalePolicy {
+    pub fn parse(value: &str) -> Result<ScalePolicy, String> {
+        match value.trim().to_lowercase().replace('-', "_").as_str() {
+            "fit" => Ok(ScalePolicy::Fit),
+            "fill" | "crop" | "cover" => Ok(ScalePolicy::Fill),
+            "no_upscale" | "none" => Ok(ScalePolicy::NoUpscale),
+            "integer" => Ok(ScalePolicy::Integer),
+            other => Err(format!("unknown scale policy: {}", other)),
+        }
+    }
+}
+
+// 拡大・縮小のアルゴリズム(ffmpegのscaleフィルタのflags)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scaler {
+    Lanczos,
+    Bicubic,
+    Bilinear,
+    Neighbor,
+}
+
+impl Scaler {
+    pub fn parse(value: &str) -> Result<Scaler, String> {
+        match value.trim().to_lowercase().as_str() {
+            "lanczos" => Ok(Scaler::Lanczos),
+            "bicubic" => Ok(Scaler::Bicubic),
+            "bilinear" => Ok(Scaler::Bilinear),
+            "neighbor" | "nearest" => Ok(Scaler::Neighbor),
+            other => Err(format!("unknown scaler: {}", other)),
+        }
+    }
+
+    pub fn flags(&self) -> &'static str {
+        match self {
+            Scaler::Lanczos => "lanczos",
+            Scaler::Bicubic => "bicubic",
+            Scaler::Bilinear => "bilinear",
+            Scaler::Neighbor => "neighbor",
+        }
+    }
+}
+
+// 色指定をffmpegの形式に揃える
+// #222 -> 0x222222, #RRGGBB -> 0xRRGGBB、色名などはそのまま
+pub fn normalize_color(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('#') {
+        Some(hex) if hex.len() == 3 => {
+            let expanded = hex.chars().flat_map(|c| [c, c]).collect::<String>();
+            format!("0x{}", expanded)
+        }
+        Some(hex) => format!("0x{}", hex),
+        None => value.to_string(),
+    }
+}
+
+// 画像をループ入力した場合のフレームレート(ffmpegのデフォルト)
+pub const FPS: i32 = 25;
+
+// 動画生成のオプション
+// テーマファイル(TOML/JSON)から読み込むことができる
+// 全ての項目は任意で、指定されていない項目はデフォルト値を使う
+// 項目名の誤りに気付けるよう、未知の項目はエラーにする
+// 生成済みの動画の再利用を判定するため、内部で設定する項目も含めてシリアライズする
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Op {
     pub w: Option<i16>,
     pub h: Option<i16>,
     pub background_color: Option<String>,
-    // pub char_prompt: Option<String>, // 未使用
+    // スライドを出力サイズに合わせる方法
+    pub scale: Option<ScalePolicy>,
+    // 拡大・縮小のアルゴリズム。未指定の場合はintegerでneighbor、それ以外はbicubic
+    pub scaler: Option<Scaler>,
+    // 余白の埋め方
+    pub fill: Option<Fill>,
+    // Fill::Imageの画像ファイル
+    pub fill_image: Option<String>,
+    // Fill::Blurのぼかしの強さ(boxblurの半径)
+    pub fill_blur: Option<i32>,
+    // Fill::Gradientの終わりの色
+    pub gradient_color: Option<String>,
     pub font: Option<String>,
+    // ASS字幕で使うフォント名(fontの書体名)
+    pub font_name: Option<String>,
     pub font_size: Option<String>,
     pub font_color: Option<String>,
     pub border_color: Option<String>,
+    // 字幕の縁取りの太さ
+    pub border_w: Option<i32>,
+    // 字幕の位置
+    pub caption_position: Option<CaptionPosition>,
+    // 位置がCustomの場合の座標(ffmpegの式)
+    pub caption_x: Option<String>,
+    pub caption_y: Option<String>,
+    // 字幕の横揃え
+    pub caption_align: Option<CaptionAlign>,
+    // 字幕の背景ボックス(例: black@0.5)。指定した場合のみ描画する
+    pub box_color: Option<String>,
+    pub box_border_w: Option<i32>,
+    // 字幕の影。指定した場合のみ描画する
+    pub shadow_color: Option<String>,
+    pub shadow_x: Option<i32>,
+    pub shadow_y: Option<i32>,
+    // 画面端からの余白(セーフエリア)
+    pub margin_x: Option<i32>,
+    pub margin_y: Option<i32>,
+    // 字幕の表示方法
+    pub caption_mode: Option<CaptionMode>,
+    // カラオケ表示で読み上げ済みの文字の色
+    pub highlight_color: Option<String>,
+    // 字幕の描画方法(カラオケ表示は常にASS)
+    pub caption_renderer: Option<CaptionRenderer>,
+    // テキストの装飾(ルビ、文字色)を解釈するか(既定は解釈しない)
+    pub markup: Option<bool>,
+    // 字幕をASSファイルで描画する場合のファイル
+    #[serde(skip_deserializing)]
+    pub subtitle_file: Option<String>,
+    #[serde(skip_deserializing)]
     pub word: Option<String>,
+    // 字幕の先頭に表示する話者名
+    #[serde(skip_deserializing)]
+    pub label: Option<String>,
+    // タイトル
+    #[serde(skip_deserializing)]
+    pub title: Option<String>,
+    // タイトルを描画するか(既定は描画しない)
+    pub show_title: Option<bool>,
+    pub title_font_size: Option<String>,
+    pub title_font_color: Option<String>,
+    pub title_border_color: Option<String>,
+    // タイトルの縦位置(ffmpegの式)
+    pub title_y: Option<String>,
+    // 動き
+    pub motion: Option<Motion>,
+    // 動きの強さ(拡大率の増分。0.1で10%)
+    pub motion_intensity: Option<f64>,
+    // セクション内での開始位置(秒)
+    // 複数のパートに分かれても動きが途切れないようにする
+    #[serde(skip_deserializing)]
+    pub motion_offset: Option<f64>,
+    // セクション全体の長さ(秒)
+    #[serde(skip_deserializing)]
+    pub motion_total: Option<f64>,
+    // 話者のキャラクター
+    #[serde(skip_deserializing)]
+    pub character: Option<character::Overlay>,
 }
 
 impl Default for Op {
@@ -25,56 +323,745 @@ impl Default for Op {
             w: Some(1920),
             h: Some(1080),
             background_color: Some("white".to_string()),
-            // char_prompt: Some("".to_string()),
+            scale: Some(ScalePolicy::NoUpscale),
+            scaler: None,
+            fill: Some(Fill::Color),
+            fill_image: None,
+            fill_blur: Some(20),
+            gradient_color: Some("black".to_string()),
             font: Some(font_filepath),
+            font_name: Some("Noto Sans JP".to_string()),
             font_size: Some("36".to_string()),
             font_color: Some("white".to_string()),
             border_color: Some("0xBBDEFB".to_string()),
+            border_w: Some(10),
+            caption_position: Some(CaptionPosition::Bottom),
+            caption_x: None,
+            caption_y: None,
+            caption_align: Some(CaptionAlign::Center),
+            box_color: None,
+            box_border_w: Some(20),
+            shadow_color: None,
+            shadow_x: Some(4),
+            shadow_y: Some(4),
+            margin_x: Some(96),
+            margin_y: Some(50),
+            caption_mode: Some(CaptionMode::Static),
+            highlight_color: Some("0xFFEB3B".to_string()),
+            caption_renderer: Some(CaptionRenderer::Drawtext),
+            markup: Some(false),
+            subtitle_file: None,
             word: Some("".to_string()),
+            label: None,
+            title: None,
+            show_title: Some(false),
+            title_font_size: Some("64".to_string()),
+            title_font_color: Some("white".to_string()),
+            title_border_color: Some("0x1565C0".to_string()),
+            title_y: Some("50".to_string()),
+            motion: None,
+            motion_intensity: Some(0.1),
+            motion_offset: None,
+            motion_total: None,
+            character: None,
         }
     }
 }
 
 impl Op {
+    // 全ての項目が未指定のオプション
+    // テーマやセクションごとの上書き設定として使う
+    pub fn empty() -> Op {
+        Op {
+            w: None,
+            h: None,
+            background_color: None,
+            scale: None,
+            scaler: None,
+            fill: None,
+            fill_image: None,
+            fill_blur: None,
+            gradient_color: None,
+            font: None,
+            font_name: None,
+            font_size: None,
+            font_color: None,
+            border_color: None,
+            border_w: None,
+            caption_position: None,
+            caption_x: None,
+            caption_y: None,
+            caption_align: None,
+            box_color: None,
+            box_border_w: None,
+            shadow_color: None,
+            shadow_x: None,
+            shadow_y: None,
+            margin_x: None,
+            margin_y: None,
+            caption_mode: None,
+            highlight_color: None,
+            caption_renderer: None,
+            markup: None,
+            subtitle_file: None,
+            word: None,
+            label: None,
+            title: None,
+            show_title: None,
+            title_font_size: None,
+            title_font_color: None,
+            title_border_color: None,
+            title_y: None,
+            motion: None,
+            motion_intensity: None,
+            motion_offset: None,
+            motion_total: None,
+            character: None,
+        }
+    }
+
+    // テーマファイルを読み込む
+    // 拡張子が.jsonの場合はJSON、それ以外はTOMLとして扱う
+    pub fn from_file(path: &Path) -> Result<Op, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read theme {}: {}", path.display(), e))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+        if is_json {
+            serde_json::from_str(&text)
+                .map_err(|e| format!("failed to parse theme {}: {}", path.display(), e))
+        } else {
+            toml::from_str(&text)
+                .map_err(|e| format!("failed to parse theme {}: {}", path.display(), e))
+        }
+    }
+
+    // 指定されている項目で上書きする
+    pub fn merge(&mut self, other: &Op) {
+        fn overwrite<T: Clone>(target: &mut Option<T>, value: &Option<T>) {
+            if value.is_some() {
+                target.clone_from(value);
+            }
+        }
+
+        overwrite(&mut self.w, &other.w);
+        overwrite(&mut self.h, &other.h);
+        overwrite(&mut self.background_color, &other.background_color);
+        overwrite(&mut self.scale, &other.scale);
+        overwrite(&mut self.scaler, &other.scaler);
+        overwrite(&mut self.fill, &other.fill);
+        overwrite(&mut self.fill_image, &other.fill_image);
+        overwrite(&mut self.fill_blur, &other.fill_blur);
+        overwrite(&mut self.gradient_color, &other.gradient_color);
+        overwrite(&mut self.font, &other.font);
+        overwrite(&mut self.font_name, &other.font_name);
+        overwrite(&mut self.font_size, &other.font_size);
+        overwrite(&mut self.font_color, &other.font_color);
+        overwrite(&mut self.border_color, &other.border_color);
+        overwrite(&mut self.border_w, &other.border_w);
+        overwrite(&mut self.caption_position, &other.caption_position);
+        overwrite(&mut self.caption_x, &other.caption_x);
+        overwrite(&mut self.caption_y, &other.caption_y);
+        overwrite(&mut self.caption_align, &other.caption_align);
+        overwrite(&mut self.box_color, &other.box_color);
+        overwrite(&mut self.box_border_w, &other.box_border_w);
+        overwrite(&mut self.shadow_color, &other.shadow_color);
+        overwrite(&mut self.shadow_x, &other.shadow_x);
+        overwrite(&mut self.shadow_y, &other.shadow_y);
+        overwrite(&mut self.margin_x, &other.margin_x);
+        overwrite(&mut self.margin_y, &other.margin_y);
+        overwrite(&mut self.caption_mode, &other.caption_mode);
+        overwrite(&mut self.highlight_color, &other.highlight_color);
+        overwrite(&mut self.caption_renderer, &other.caption_renderer);
+        overwrite(&mut self.markup, &other.markup);
+        overwrite(&mut self.subtitle_file, &other.subtitle_file);
+        overwrite(&mut self.word, &other.word);
+        overwrite(&mut self.label, &other.label);
+        overwrite(&mut self.title, &other.title);
+        overwrite(&mut self.show_title, &other.show_title);
+        overwrite(&mut self.title_font_size, &other.title_font_size);
+        overwrite(&mut self.title_font_color, &other.title_font_color);
+        overwrite(&mut self.title_border_color, &other.title_border_color);
+        overwrite(&mut self.title_y, &other.title_y);
+        overwrite(&mut self.motion, &other.motion);
+        overwrite(&mut self.motion_intensity, &other.motion_intensity);
+        overwrite(&mut self.motion_offset, &other.motion_offset);
+        overwrite(&mut self.motion_total, &other.motion_total);
+        overwrite(&mut self.character, &other.character);
+    }
+
+    // key=value形式の指定で項目を設定する
+    // スクリプトのヘッダー属性から部分的なオプションを作るために使う
+    // 対応していないkeyの場合はfalseを返す
+    pub fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        let value = value.trim();
+        let parse_i16 = |value: &str| {
+            value
+                .parse::<i16>()
+                .map_err(|e| format!("invalid {}: {}", key, e))
+        };
+        let parse_i32 = |value: &str| {
+            value
+                .parse::<i32>()
+                .map_err(|e| format!("invalid {}: {}", key, e))
+        };
+        let parse_f64 = |value: &str| {
+            value
+                .parse::<f64>()
+                .map_err(|e| format!("invalid {}: {}", key, e))
+        };
+
+        match key {
+            "w" | "width" => self.w = Some(parse_i16(value)?),
+            "h" | "height" => self.h = Some(parse_i16(value)?),
+            "bg" | "background" | "background_color" => {
+                self.background_color = Some(normalize_color(value))
+            }
+            "scale" | "scale_policy" => self.scale = Some(ScalePolicy::parse(value)?),
+            "scaler" => self.scaler = Some(Scaler::parse(value)?),
+            "fill" => self.fill = Some(Fill::parse(value)?),
+            // 画像を指定した場合は画像で埋める
+            "fill_image" => {
+                self.fill_image = Some(value.to_string());
+                self.fill = Some(Fill::Image);
+            }
+            "fill_blur" | "blur" => self.fill_blur = Some(parse_i32(value)?),
+            "gradient" | "gradient_color" => self.gradient_color = Some(normalize_color(value)),
+            "font" => self.font = Some(value.to_string()),
+            "font_name" => self.font_name = Some(value.to_string()),
+            "font_size" | "size" => self.font_size = Some(value.to_string()),
+            "font_color" | "color" => self.font_color = Some(normalize_color(value)),
+            "border_color" | "border" => self.border_color = Some(normalize_color(value)),
+            "border_w" => self.border_w = Some(parse_i32(value)?),
+            "position" | "caption_position" => {
+                self.caption_position = Some(CaptionPosition::parse(value)?)
+            }
+            // 座標を指定した場合は位置をCustomとする
+            "x" | "caption_x" => {
+                self.caption_x = Some(value.to_string());
+                self.caption_position = Some(CaptionPosition::Custom);
+            }
+            "y" | "caption_y" => {
+                self.caption_y = Some(value.to_string());
+                self.caption_position = Some(CaptionPosition::Custom);
+            }
+            "align" | "caption_align" => self.caption_align = Some(CaptionAlign::parse(value)?),
+            "box" | "box_color" => self.box_color = Some(normalize_color(value)),
+            "box_border_w" => self.box_border_w = Some(parse_i32(value)?),
+            "shadow" | "shadow_color" => self.shadow_color = Some(normalize_color(value)),
+            "shadow_x" => self.shadow_x = Some(parse_i32(value)?),
+            "shadow_y" => self.shadow_y = Some(parse_i32(value)?),
+            "margin_x" => self.margin_x = Some(parse_i32(value)?),
+            "margin_y" => self.margin_y = Some(parse_i32(value)?),
+            "caption_mode" | "mode" => self.caption_mode = Some(CaptionMode::parse(value)?),
+            "karaoke" => {
+                self.caption_mode = Some(if value == "false" || value == "off" {
+                    CaptionMode::Static
+                } else {
+                    CaptionMode::Karaoke
+                })
+            }
+            "highlight" | "highlight_color" => self.highlight_color = Some(normalize_color(value)),
+            "renderer" | "caption_renderer" => {
+                self.caption_renderer = Some(CaptionRenderer::parse(value)?)
+            }
+            "markup" => self.markup = Some(value != "false" && value != "off"),
+            "show_title" => self.show_title = Some(value != "false" && value != "off"),
+            "title_font_size" | "title_size" => self.title_font_size = Some(value.to_string()),
+            "title_font_color" | "title_color" => {
+                self.title_font_color = Some(normalize_color(value))
+            }
+            "title_border_color" | "title_border" => {
+                self.title_border_color = Some(normalize_color(value))
+            }
+            "title_y" => self.title_y = Some(value.to_string()),
+            "motion" => self.motion = Motion::parse(value)?,
+            "motion_intensity" => self.motion_intensity = Some(parse_f64(value)?),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
     pub fn set_word(&mut self, word: &str) {
         self.word = Some(word.to_string());
     }
 
+    // 字幕をASSファイルで描画するか
+    pub fn uses_markup(&self) -> bool {
+        self.markup == Some(true)
+    }
+
+    pub fn uses_subtitle_file(&self) -> bool {
+        self.caption_mode == Some(CaptionMode::Karaoke)
+            || self.caption_renderer == Some(CaptionRenderer::Ass)
+    }
+
+    // テキスト単位の動画のフィルタ
+    // キャラクターの画像は3番目以降の入力(入力番号2〜)とする
     pub fn create_filter_complex(&self) -> String {
+        match self.character.as_ref() {
+            Some(character) => [
+                self.create_base_filter(),
+                character.create_filter("[bg]", "[chr]", 2, 0.0, None),
+                ";".to_string(),
+                self.create_caption_filter("[chr]", "[out2]", None),
+            ]
+            .join(""),
+            None => [
+                self.create_base_filter(),
+                self.create_caption_filter("[bg]", "[out2]", None),
+            ]
+            .join(""),
+        }
+    }
+
+    // スライドを出力サイズに合わせる基本フィルタ
+    // 出力ラベルは[bg]
+    pub fn create_base_filter(&self) -> String {
         let width = self.w.unwrap_or_default();
         let height = self.h.unwrap_or_default();
         let binding = self.background_color.as_deref().unwrap_or_default();
         let background_color = binding;
+
+        let mut args = Vec::new();
+        let motion_filter = self.create_motion_filter();
+        let title_filter = self.create_title_filter();
+        let pad_label = if motion_filter.is_some() {
+            "pad"
+        } else if title_filter.is_some() {
+            "title"
+        } else {
+            "bg"
+        };
+        let basic_filer = match self.fill.unwrap_or(Fill::Color) {
+            Fill::Color => format!(
+                "[0]{},pad={width}:{height}:({width}-iw)/2:({height}-ih)/2:{background_color}[{pad_label}];",
+                self.create_scale_filter()
+            ),
+            fill => self.create_fill_filter(fill, pad_label),
+        };
+        args.push(basic_filer.as_str());
+
+        if let Some(motion_filter) = motion_filter.as_deref() {
+            args.push(motion_filter);
+        }
+
+        if let Some(title_filter) = title_filter.as_deref() {
+            args.push(title_filter);
+        }
+
+        args.join("")
+    }
+
+    // 余白を背景で埋めるフィルタ
+    // 背景の上にスライドを中央に重ね、出力ラベルはpad_label
+    fn create_fill_filter(&self, fill: Fill, pad_label: &str) -> String {
+        let width = self.w.unwrap_or_default();
+        let height = self.h.unwrap_or_default();
+        let background_color = self.background_color.as_deref().unwrap_or("black");
+        // 出力全体を覆うように拡大して切り抜く
+        let cover = format!(
+            "scale={width}:{height}:force_original_aspect_ratio=increase,crop={width}:{height},setsar=1"
+        );
+
+        let (slide, background) = match fill {
+            Fill::Blur => {
+                let blur = self.fill_blur.unwrap_or(20);
+                (
+                    "[fill0]".to_string(),
+                    format!("[0]split[fill0][fill1];[fill1]{cover},boxblur={blur}:2[fillbg];"),
+                )
+            }
+            // 画像が指定されていない場合は背景色で埋める
+            Fill::Image if self.fill_image.is_some() => (
+                "[0]".to_string(),
+                format!(
+                    "movie='{}':loop=0,setpts=N/({FPS}*TB),{cover}[fillbg];",
+                    subtitle::filter_path(self.fill_image.as_deref().unwrap_or_default())
+                ),
+            ),
+            Fill::Image | Fill::Color => (
+                "[0]".to_string(),
+                format!("color=c={background_color}:s={width}x{height}:r={FPS}[fillbg];"),
+            ),
+            Fill::Gradient => {
+                let gradient_color = self.gradient_color.as_deref().unwrap_or("black");
+                (
+                    "[0]".to_string(),
+                    format!(
+                        "gradients=s={width}x{height}:c0={background_color}:c1={gradient_color}:\
+                        x0=0:y0=0:x1=0:y1={height}:r={FPS}[fillbg];"
+                    ),
+                )
+            }
+        };
+
+        format!(
+            "{background}{slide}{}[fillfg];\
+            [fillbg][fillfg]overlay=x=(W-w)/2:y=(H-h)/2:shortest=1,setsar=1[{pad_label}];",
+            self.create_scale_filter()
+        )
+    }
+
+    // スライドを出力サイズに合わせるscaleフィルタ
+    fn create_scale_filter(&self) -> String {
+        let width = self.w.unwrap_or_default();
+        let height = self.h.unwrap_or_default();
+        let policy = self.scale.unwrap_or(ScalePolicy::NoUpscale);
+        let scaler = self.scaler.unwrap_or(match policy {
+            ScalePolicy::Integer => Scaler::Neighbor,
+            _ => Scaler::Bicubic,
+        });
+        let flags = scaler.flags();
+
+        match policy {
+            ScalePolicy::Fit => {
+                format!("scale={width}:{height}:force_original_aspect_ratio=decrease:flags={flags}")
+            }
+            ScalePolicy::Fill => format!(
+                "scale={width}:{height}:force_original_aspect_ratio=increase:flags={flags},crop={width}:{height}"
+            ),
+            ScalePolicy::NoUpscale => format!(
+                "scale=w='min({width},iw)':h='min({height},ih)':force_original_aspect_ratio=decrease:flags={flags}"
+            ),
+            // 収まる最大の整数倍(1未満の場合は縮小して収める)
+            ScalePolicy::Integer => {
+                let factor = format!("max(1,min(floor({width}/iw),floor({height}/ih)))");
+                format!(
+                    "scale=w='min({width},iw*{factor})':h='min({height},ih*{factor})':force_original_aspect_ratio=decrease:flags={flags}"
+                )
+            }
+        }
+    }
+
+    // タイトルを描画するフィルタ
+    // 入力ラベルは[title]、出力ラベルは[bg]
+    // show_titleを指定した場合のみ描画する
+    fn create_title_filter(&self) -> Option<String> {
+        if self.show_title != Some(true) {
+            return None;
+        }
+        let title = self.title.as_deref().filter(|title| !title.is_empty())?;
+        let font = self.font.as_deref().unwrap_or_default();
+        let font_size = self.title_font_size.as_deref().unwrap_or("64");
+        let font_color = self.title_font_color.as_deref().unwrap_or("white");
+        let border_color = self.title_border_color.as_deref().unwrap_or("black");
+        let y = self.title_y.as_deref().unwrap_or("50");
+
+        Some(format!(
+            "[title]drawtext=fontfile='{font}':\
+        fontsize={font_size}:\
+        fontcolor={font_color}:\
+        borderw=8:\
+        bordercolor={border_color}:\
+        text='{title}':\
+        x=(W-text_w)/2:\
+        y={y}[bg];"
+        ))
+    }
+
+    // 字幕を描画するフィルタ
+    // enable: 表示する時間帯(秒)。Noneの場合は常に表示
+    pub fn create_caption_filter(
+        &self,
+        input_label: &str,
+        output_label: &str,
+        enable: Option<(f64, f64)>,
+    ) -> String {
+        // ASSファイルの字幕はファイル内の時刻で表示される
+        if let Some(subtitle_file) = self.subtitle_file.as_deref() {
+            return self.create_subtitle_filter(subtitle_file, input_label, output_label);
+        }
+
         let font = self.font.as_deref().unwrap_or_default();
         let font_size = self.font_size.as_deref().unwrap_or_default();
         let font_color = self.font_color.as_deref().unwrap_or_default();
         let border_color = self.border_color.as_deref().unwrap_or_default();
+        let border_w = self.border_w.unwrap_or(10);
+        let (x, y) = self.caption_xy();
+        let enable = match enable {
+            Some((start, end)) => format!(":enable='gte(t,{start})*lt(t,{end})'"),
+            None => "".to_string(),
+        };
 
-        let mut args = Vec::new();
-        let basic_filer = format!(
-            "[0]scale=w='min({width},iw)':h='min({height},ih)':
-        force_original_aspect_ratio=decrease,\
-        pad={width}:{height}:({width}-iw)/
-        2:({height}-ih)/
-        2:{background_color}[bg];",
-        );
-        args.push(basic_filer.as_str());
+        // 背景ボックスと影
+        let mut decoration = String::new();
+        if let Some(box_color) = self.box_color.as_deref() {
+            let box_border_w = self.box_border_w.unwrap_or(20);
+            decoration.push_str(&format!(
+                "box=1:boxcolor={box_color}:boxborderw={box_border_w}:"
+            ));
+        }
+        if let Some(shadow_color) = self.shadow_color.as_deref() {
+            let shadow_x = self.shadow_x.unwrap_or(4);
+            let shadow_y = self.shadow_y.unwrap_or(4);
+            decoration.push_str(&format!(
+                "shadowcolor={shadow_color}:shadowx={shadow_x}:shadowy={shadow_y}:"
+            ));
+        }
 
-        let word = self.word.as_deref().unwrap_or("");
-        let prompt = format!(
-            "[bg]drawtext=fontfile='{font}':\
+        // drawtextではルビや文字色を表示できないため、装飾を取り除く
+        let word = subtitle::plain_text(self.word.as_deref().unwrap_or(""), self.uses_markup());
+        let word = match self.label.as_deref() {
+            Some(label) => format!("{}：{}", label, word),
+            None => word,
+        };
+        format!(
+            "{input_label}drawtext=fontfile='{font}':\
         fontsize={font_size}:\
         fontcolor={font_color}@0.9:\
-        borderw=10:\
+        borderw={border_w}:\
         bordercolor={border_color}:\
+        {decoration}\
         text='{word}':\
-        x=(W-text_w)/2:\
-        y=(H-text_h-50):\
-        wrap_unicode[out2]"
+        x='{x}':\
+        y='{y}':\
+        wrap_unicode{enable}{output_label}"
+        )
+    }
+
+    // ASSファイルの字幕を焼き込むフィルタ
+    // fontsdirにfontの場所を渡し、font_nameの書体を探させる
+    fn create_subtitle_filter(
+        &self,
+        subtitle_file: &str,
+        input_label: &str,
+        output_label: &str,
+    ) -> String {
+        let subtitle_file = subtitle::filter_path(subtitle_file);
+        let fonts_dir = self
+            .font
+            .as_deref()
+            .and_then(|font| Path::new(font).parent())
+            .map(|dir| {
+                format!(
+                    ":fontsdir='{}'",
+                    subtitle::filter_path(&dir.to_string_lossy())
+                )
+            })
+            .unwrap_or_default();
+
+        format!("{input_label}subtitles=filename='{subtitle_file}'{fonts_dir}{output_label}")
+    }
+
+    // 字幕の座標(ffmpegの式)
+    // 位置のプリセット、横揃え、セーフエリアの余白から決める
+    fn caption_xy(&self) -> (String, String) {
+        let margin_x = self.margin_x.unwrap_or(0);
+        let margin_y = self.margin_y.unwrap_or(50);
+
+        let x = match self.caption_align.unwrap_or(CaptionAlign::Center) {
+            CaptionAlign::Left => format!("{margin_x}"),
+            CaptionAlign::Center => "(W-text_w)/2".to_string(),
+            CaptionAlign::Right => format!("(W-text_w-{margin_x})"),
+        };
+        let y = match self.caption_position.unwrap_or(CaptionPosition::Bottom) {
+            CaptionPosition::Bottom => format!("(H-text_h-{margin_y})"),
+            CaptionPosition::Top => format!("{margin_y}"),
+            CaptionPosition::Center => "(H-text_h)/2".to_string(),
+            CaptionPosition::Custom => {
+                return (
+                    self.caption_x.clone().unwrap_or(x),
+                    self.caption_y
+                        .clone()
+                        .unwrap_or(format!("(H-text_h-{margin_y})")),
+                );
+            }
+        };
+
+        (x, y)
+    }
+
+    // zoompanによる動きのフィルタ
+    // 進捗はセクション全体に対する割合で計算し、パートをまたいでも連続させる
+    fn create_motion_filter(&self) -> Option<String> {
+        let motion = self.motion?;
+        let width = self.w.unwrap_or_default();
+        let height = self.h.unwrap_or_default();
+        let intensity = self.motion_intensity.unwrap_or(0.1);
+        let offset_frames = self.motion_offset.unwrap_or(0.0) * FPS as f64;
+        let total_frames = (self.motion_total.unwrap_or(0.0) * FPS as f64).max(1.0);
+
+        // 0から1へ進む進捗
+        let progress = format!("min(({offset_frames}+on)/{total_frames},1)");
+        let center_x = "iw/2-(iw/zoom/2)".to_string();
+        let center_y = "ih/2-(ih/zoom/2)".to_string();
+
+        let (zoom, x, y) = match motion {
+            Motion::None => return None,
+            Motion::ZoomIn => (format!("1+{intensity}*{progress}"), center_x, center_y),
+            Motion::ZoomOut => (format!("1+{intensity}*(1-{progress})"), center_x, center_y),
+            Motion::PanLeft => (
+                format!("1+{intensity}"),
+                format!("(iw-iw/zoom)*(1-{progress})"),
+                center_y,
+            ),
+            Motion::PanRight => (
+                format!("1+{intensity}"),
+                format!("(iw-iw/zoom)*{progress}"),
+                center_y,
+            ),
+            Motion::PanUp => (
+                format!("1+{intensity}"),
+                center_x,
+                format!("(ih-ih/zoom)*(1-{progress})"),
+            ),
+            Motion::PanDown => (
+                format!("1+{intensity}"),
+                center_x,
+                format!("(ih-ih/zoom)*{progress}"),
+            ),
+        };
+
+        let output_label = if self.create_title_filter().is_some() {
+            "title"
+        } else {
+            "bg"
+        };
+        Some(format!(
+            "[pad]zoompan=z='{zoom}':x='{x}':y='{y}':d=1:s={width}x{height}:fps={FPS}[{output_label}];"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_merge() {
+        let project_dir = env!("CARGO_MANIFEST_DIR");
+        let theme_path = Path::new(project_dir).join("resource/themes/dark.toml");
+        let theme = Op::from_file(&theme_path).unwrap();
+        assert_eq!(theme.background_color.as_deref(), Some("0x222222"));
+        assert!(theme.font.is_none());
+
+        let mut op = Op::default();
+        op.merge(&theme);
+        assert_eq!(op.background_color.as_deref(), Some("0x222222"));
+        assert_eq!(op.font_size.as_deref(), Some("40"));
+        // テーマで指定していない項目はデフォルト値のまま
+        assert!(op.font.is_some());
+
+        let json: Op = serde_json::from_str(r#"{"font_color": "black"}"#).unwrap();
+        op.merge(&json);
+        assert_eq!(op.font_color.as_deref(), Some("black"));
+
+        // タイトルは指定した場合のみ描画する
+        let mut op = Op {
+            title: Some("タイトル".to_string()),
+            ..Default::default()
+        };
+        assert!(op.create_title_filter().is_none());
+        op.merge(&theme);
+        assert!(
+            op.create_title_filter()
+                .unwrap()
+                .contains("text='タイトル'")
         );
+    }
 
-        args.push(prompt.as_str());
+    #[test]
+    fn test_motion_filter() {
+        let mut op = Op {
+            motion: Some(Motion::ZoomIn),
+            motion_offset: Some(1.0),
+            motion_total: Some(4.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            op.create_motion_filter().unwrap(),
+            format!(
+                "[pad]zoompan=z='1+0.1*min(({}+on)/{},1)':x='iw/2-(iw/zoom/2)':y='ih/2-(ih/zoom/2)':d=1:s=1920x1080:fps={}[bg];",
+                FPS,
+                4 * FPS,
+                FPS
+            )
+        );
 
-        args.join("")
+        // noneは既定の動きを上書きして止める
+        let mut header = Op::empty();
+        header.motion = Motion::parse("none").unwrap();
+        op.merge(&header);
+        assert_eq!(op.motion, Some(Motion::None));
+        assert!(op.create_motion_filter().is_none());
+    }
+
+    #[test]
+    fn test_set() {
+        let mut op = Op::empty();
+        assert!(op.set("font_color", "black").unwrap());
+        assert!(op.set("position", "top").unwrap());
+        assert!(op.set("bg", "#222").unwrap());
+        assert!(!op.set("unknown", "1").unwrap());
+        assert!(op.set("position", "left").is_err());
+
+        assert_eq!(op.font_color.as_deref(), Some("black"));
+        assert_eq!(op.caption_position, Some(CaptionPosition::Top));
+        assert_eq!(op.background_color.as_deref(), Some("0x222222"));
+        assert!(op.font_size.is_none());
+    }
+
+    #[test]
+    fn test_caption_xy() {
+        let mut op = Op::default();
+        assert_eq!(
+            op.caption_xy(),
+            ("(W-text_w)/2".to_string(), "(H-text_h-50)".to_string())
+        );
+
+        op.set("align", "right").unwrap();
+        op.set("position", "top").unwrap();
+        op.set("margin_y", "80").unwrap();
+        assert_eq!(
+            op.caption_xy(),
+            ("(W-text_w-96)".to_string(), "80".to_string())
+        );
+
+        op.set("y", "H/3").unwrap();
+        assert_eq!(op.caption_position, Some(CaptionPosition::Custom));
+        assert_eq!(op.caption_xy().1, "H/3");
+
+        op.set("box", "black@0.5").unwrap();
+        let filter = op.create_caption_filter("[bg]", "[out2]", None);
+        assert!(filter.contains("box=1:boxcolor=black@0.5:boxborderw=20:"));
+        assert!(!filter.contains("shadowcolor"));
+
+        // カンマを含む式はフィルタの区切りにならないよう引用符で囲む
+        op.set("x", "if(gt(t,1),100,200)").unwrap();
+        let filter = op.create_caption_filter("[bg]", "[out2]", None);
+        assert!(filter.contains(":x='if(gt(t,1),100,200)':y='H/3':"));
+    }
+
+    #[test]
+    fn test_fill_filter() {
+        let mut op = Op::default();
+        assert_eq!(
+            op.create_base_filter(),
+            "[0]scale=w='min(1920,iw)':h='min(1080,ih)':force_original_aspect_ratio=decrease:flags=bicubic,pad=1920:1080:(1920-iw)/2:(1080-ih)/2:white[bg];"
+        );
+
+        op.set("fill", "blur").unwrap();
+        let filter = op.create_base_filter();
+        assert!(filter.starts_with("[0]split[fill0][fill1];[fill1]scale=1920:1080:force_original_aspect_ratio=increase,crop=1920:1080,setsar=1,boxblur=20:2[fillbg];[fill0]scale="));
+
+        op.set("scale", "fit").unwrap();
+        op.set("scaler", "lanczos").unwrap();
+        assert!(op.create_base_filter().contains(
+            "[fill0]scale=1920:1080:force_original_aspect_ratio=decrease:flags=lanczos[fillfg];"
+        ));
+        assert!(
+            filter
+                .ends_with("[fillbg][fillfg]overlay=x=(W-w)/2:y=(H-h)/2:shortest=1,setsar=1[bg];")
+        );
+
+        op.set("fill_image", "C:\\bg.png").unwrap();
+        assert!(
+            op.create_base_filter()
+                .starts_with("movie='C\\:/bg.png':loop=0")
+        );
     }
 }
@@ -1,13 +1,29 @@
 use std::{fs::File, io::Write};
 
 use chrono::TimeDelta;
-use voicevox_client::Client;
+
+use crate::slide::{loudness, voicevox};
+
+// 指定がない場合はデフォルトのボイスIDを使う
+pub fn resolve_voice_id(voice_id: Option<i32>) -> i32 {
+    match voice_id {
+        Some(voice_id) => voice_id,
+        None => std::env::var("DEFAULT_VOICEVOX_VOICE_ID")
+            .unwrap()
+            .parse::<i32>()
+            .unwrap(),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Data {
     pub voice_id: Option<i32>,
     pub filepath: String,
     pub duration: TimeDelta,
+    // ラウドネス正規化を行った場合の計測値
+    pub loudness: Option<loudness::Measurement>,
+    // 音声合成に使ったクエリ(JSON)。モーラのタイミングを字幕などに使う
+    pub query_filepath: Option<String>,
 }
 
 impl Data {
@@ -16,13 +32,26 @@ impl Data {
             voice_id,
             filepath: outputpath.to_string(),
             duration: TimeDelta::zero(),
+            loudness: None,
+            query_filepath: None,
         }
     }
 
-    pub async fn create_voice(&mut self, text: &str) -> Result<Self, String> {
+    // 保存したクエリを読み込む
+    pub fn query(&self) -> Option<voicevox::AudioQuery> {
+        let query_filepath = self.query_filepath.as_deref()?;
+        voicevox::AudioQuery::from_file(query_filepath).ok()
+    }
+
+    // prosody: 読み上げ方の調整。未指定の項目はVOICEVOXの既定値
+    pub async fn create_voice(
+        &mut self,
+        text: &str,
+        prosody: &voicevox::Prosody,
+    ) -> Result<Self, String> {
         // ここで音声化処理を行う
         // 例）音声化処理を行い、durationをセットする
-        let duration_delta = match self.voicebox(text).await {
+        let duration_delta = match self.voicebox(text, prosody).await {
             Ok(duration) => duration,
             Err(e) => {
                 return Err(format!("Error: {}", e));
@@ -30,34 +59,40 @@ impl Data {
         };
         println!("duration: {:?}", duration_delta);
         self.duration = duration_delta;
+
+        // 音声ごとのラウドネス正規化
+        if loudness::Mode::from_env() == loudness::Mode::Clip {
+            let target = loudness::Target::from_env();
+            let measured = loudness::normalize_clip(&self.filepath, &target).await?;
+            self.loudness = Some(measured);
+        }
         Ok(self.clone())
     }
 
     // 出力ファイルに対して音声を生成する
-    // voicevox_clientを使用して音声を生成する
-    async fn voicebox(&self, text: &str) -> Result<TimeDelta, String> {
-        let base_path = std::env::var("DEFAULT_VOICEVOX_SERVER_URL").unwrap();
-        let client = Client::new(base_path);
+    // VOICEVOXのAPIを使用して音声を生成する
+    // 合成に使ったクエリは音声ファイルと同じ場所に保存する
+    async fn voicebox(
+        &mut self,
+        text: &str,
+        prosody: &voicevox::Prosody,
+    ) -> Result<TimeDelta, String> {
+        let client = voicevox::Client::from_env()?;
 
-        let default_voice_id = std::env::var("DEFAULT_VOICEVOX_VOICE_ID")
-            .unwrap()
-            .parse::<i32>()
-            .unwrap();
-        let voice_id = match self.voice_id {
-            Some(voice_id) => voice_id,
-            None => default_voice_id,
-        };
+        let voice_id = resolve_voice_id(self.voice_id);
 
         // クエリ生成
-        let audio_query = match client.create_audio_query(text, voice_id, None).await {
+        let mut audio_query = match client.create_audio_query(text, voice_id).await {
             Ok(audio_query) => audio_query,
             Err(e) => {
                 return Err(format!("create query: {}", e));
             }
         };
 
+        audio_query.apply(prosody);
+
         // 音声生成
-        let audio = match audio_query.synthesis(voice_id).await {
+        let audio = match client.synthesis(&audio_query, voice_id).await {
             Ok(audio) => audio,
             Err(e) => {
                 return Err(format!("create audio: {}", e));
@@ -70,6 +105,13 @@ impl Data {
         let mut file = File::create(&self.filepath).map_err(|e| e.to_string())?;
         file.write_all(&audio).map_err(|e| e.to_string())?;
 
+        let query_filepath = std::path::Path::new(&self.filepath)
+            .with_extension("query.json")
+            .to_string_lossy()
+            .into_owned();
+        audio_query.save(&query_filepath)?;
+        self.query_filepath = Some(query_filepath);
+
         // get audio play time
         let duration = audio.len() as f32 / 48000.0;
         let time_delta = chrono::Duration::milliseconds((duration * 1000.0) as i64);
@@ -122,7 +164,9 @@ mod tests {
         println!("project_dir: {}", target_file);
 
         let mut data = Data::new(Some(14), target_file);
-        let result = data.create_voice("テストしています、いかがですか？").await;
+        let result = data
+            .create_voice("テストしています、いかがですか？", &Default::default())
+            .await;
         assert!(result.is_ok(), "Error: {:?}", result);
     }
 }
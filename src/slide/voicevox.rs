@@ -0,0 +1,461 @@
+use std::time::Duration;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+// VOICEVOX ENGINE(互換エンジン含む)のAPIクライアント
+// 音声合成クエリのモーラごとの長さを字幕のタイミングに使うため、クエリを型として扱う
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mora {
+    pub text: String,
+    #[serde(default)]
+    pub consonant: Option<String>,
+    #[serde(default)]
+    pub consonant_length: Option<f64>,
+    pub vowel: String,
+    pub vowel_length: f64,
+    pub pitch: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccentPhrase {
+    pub moras: Vec<Mora>,
+    pub accent: i32,
+    #[serde(default)]
+    pub pause_mora: Option<Mora>,
+    #[serde(default)]
+    pub is_interrogative: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioQuery {
+    pub accent_phrases: Vec<AccentPhrase>,
+    #[serde(rename = "speedScale")]
+    pub speed_scale: f64,
+    #[serde(rename = "pitchScale")]
+    pub pitch_scale: f64,
+    #[serde(rename = "intonationScale")]
+    pub intonation_scale: f64,
+    #[serde(rename = "volumeScale")]
+    pub volume_scale: f64,
+    #[serde(rename = "prePhonemeLength")]
+    pub pre_phoneme_length: f64,
+    #[serde(rename = "postPhonemeLength")]
+    pub post_phoneme_length: f64,
+    #[serde(rename = "outputSamplingRate")]
+    pub output_sampling_rate: i32,
+    #[serde(rename = "outputStereo")]
+    pub output_stereo: bool,
+    #[serde(default)]
+    pub kana: Option<String>,
+    // エンジンのバージョンによって追加される項目はそのまま送り返す
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+// 読み上げ方の調整
+// 指定した項目のみクエリの値を置き換える
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Prosody {
+    // 話速(1.0が標準)
+    pub speed: Option<f64>,
+    // 音高(0.0が標準)
+    pub pitch: Option<f64>,
+    // 抑揚(1.0が標準)
+    pub intonation: Option<f64>,
+    // 音量(1.0が標準)
+    pub volume: Option<f64>,
+    // 読み上げ後の無音(秒)
+    pub pause: Option<f64>,
+}
+
+// 発声されるモーラの時間帯
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoraTiming {
+    pub text: String,
+    pub vowel: String,
+    // 秒
+    pub start: f64,
+    pub end: f64,
+}
+
+impl AudioQuery {
+    pub fn from_file(path: &str) -> Result<AudioQuery, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&text).map_err(|e| format!("failed to parse query {}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+
+    // 読み上げ方の調整を反映する
+    pub fn apply(&mut self, prosody: &Prosody) {
+        if let Some(speed) = prosody.speed {
+            self.speed_scale = speed;
+        }
+        if let Some(pitch) = prosody.pitch {
+            self.pitch_scale = pitch;
+        }
+        if let Some(intonation) = prosody.intonation {
+            self.intonation_scale = intonation;
+        }
+        if let Some(volume) = prosody.volume {
+            self.volume_scale = volume;
+        }
+        // 無音の長さも話速で割られるため、話速を掛けておく
+        if let Some(pause) = prosody.pause {
+            self.post_phoneme_length += pause * self.speed_scale;
+        }
+    }
+
+    // モーラごとの発声時間帯(秒)
+    // 話速を反映し、句読点などのポーズはモーラに含めない
+    pub fn mora_timings(&self) -> Vec<MoraTiming> {
+        let speed = if self.speed_scale > 0.0 {
+            self.speed_scale
+        } else {
+            1.0
+        };
+        let length =
+            |mora: &Mora| (mora.consonant_length.unwrap_or(0.0) + mora.vowel_length) / speed;
+
+        let mut timings = vec![];
+        let mut elapsed = self.pre_phoneme_length / speed;
+        for accent_phrase in self.accent_phrases.iter() {
+            for mora in accent_phrase.moras.iter() {
+                let end = elapsed + length(mora);
+                timings.push(MoraTiming {
+                    text: mora.text.clone(),
+                    vowel: mora.vowel.clone(),
+                    start: elapsed,
+                    end,
+                });
+                elapsed = end;
+            }
+            if let Some(pause_mora) = accent_phrase.pause_mora.as_ref() {
+                elapsed += length(pause_mora);
+            }
+        }
+
+        timings
+    }
+}
+
+// リクエストのタイムアウトと再試行
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    // リクエストごとのタイムアウト
+    pub timeout: Duration,
+    // 失敗後に再試行する回数
+    pub retries: u32,
+    // 最初の再試行までの待ち時間。再試行ごとに2倍にする
+    pub backoff: Duration,
+    // 待ち時間の上限
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Duration::from_secs(60),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // DEFAULT_VOICEVOX_TIMEOUT(秒)、DEFAULT_VOICEVOX_RETRIES、DEFAULT_VOICEVOX_BACKOFF_MS
+    pub fn from_env() -> Result<RetryPolicy, String> {
+        fn parse<T: std::str::FromStr>(key: &str) -> Result<Option<T>, String> {
+            match std::env::var(key) {
+                Ok(value) if !value.trim().is_empty() => value
+                    .trim()
+                    .parse::<T>()
+                    .map(Some)
+                    .map_err(|_| format!("invalid {}: {}", key, value)),
+                _ => Ok(None),
+            }
+        }
+
+        let mut policy = RetryPolicy::default();
+        if let Some(timeout) = parse::<f64>("DEFAULT_VOICEVOX_TIMEOUT")? {
+            policy.timeout = Duration::try_from_secs_f64(timeout)
+                .map_err(|e| format!("invalid DEFAULT_VOICEVOX_TIMEOUT: {}", e))?;
+            // 0秒ではすべてのリクエストがタイムアウトする
+            if policy.timeout.is_zero() {
+                return Err(format!(
+                    "invalid DEFAULT_VOICEVOX_TIMEOUT: {} (must be greater than 0)",
+                    timeout
+                ));
+            }
+        }
+        if let Some(retries) = parse::<u32>("DEFAULT_VOICEVOX_RETRIES")? {
+            policy.retries = retries;
+        }
+        if let Some(backoff) = parse::<u64>("DEFAULT_VOICEVOX_BACKOFF_MS")? {
+            policy.backoff = Duration::from_millis(backoff);
+        }
+        Ok(policy)
+    }
+
+    // attempt回目(0始まり)の再試行までの待ち時間
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+}
+
+// 再試行する失敗か
+// 接続の失敗、タイムアウト、送受信の途中での切断、サーバーエラー(5xx)、リクエスト過多(429)
+fn is_retryable(e: &reqwest::Error) -> bool {
+    if e.is_connect() || e.is_timeout() || e.is_request() || e.is_body() {
+        return true;
+    }
+    e.status().is_some_and(|status| {
+        status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+    })
+}
+
+pub struct Client {
+    base_path: String,
+    client: reqwest::Client,
+    policy: RetryPolicy,
+}
+
+impl Client {
+    pub fn with_policy(base_path: String, policy: RetryPolicy) -> Result<Client, String> {
+        let client = reqwest::Client::builder()
+            .timeout(policy.timeout)
+            .connect_timeout(policy.timeout.min(Duration::from_secs(10)))
+            .build()
+            .map_err(|e| format!("failed to create voicevox client: {}", e))?;
+        Ok(Client {
+            base_path,
+            client,
+            policy,
+        })
+    }
+
+    // 環境変数のタイムアウトと再試行の設定を使う
+    pub fn from_env() -> Result<Client, String> {
+        let base_path = std::env::var("DEFAULT_VOICEVOX_SERVER_URL")
+            .map_err(|_| "DEFAULT_VOICEVOX_SERVER_URL is not set".to_string())?;
+        Client::with_policy(base_path, RetryPolicy::from_env()?)
+    }
+
+    // リクエストを送信してレスポンスの本文を返す
+    // 失敗した場合は待ち時間を延ばしながら再試行する。本文の受信中の切断も再試行する
+    // 4xxなど再試行しても変わらない失敗はすぐに返す
+    async fn send(&self, request: impl Fn() -> reqwest::RequestBuilder) -> Result<Vec<u8>, String> {
+        let mut attempt = 0;
+        loop {
+            let result = match request().send().await {
+                Ok(response) => match response.error_for_status() {
+                    Ok(response) => response.bytes().await,
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(body) => return Ok(body.to_vec()),
+                Err(e) if attempt < self.policy.retries && is_retryable(&e) => {
+                    let delay = self.policy.delay(attempt);
+                    warn!(
+                        "voicevox request failed, retry in {:?} ({}/{}): {}",
+                        delay,
+                        attempt + 1,
+                        self.policy.retries,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    // エンジンのバージョン
+    pub async fn version(&self) -> Result<String, String> {
+        let body = self
+            .send(|| self.client.get(format!("{}/version", self.base_path)))
+            .await?;
+        serde_json::from_slice::<String>(&body).map_err(|e| e.to_string())
+    }
+
+    // エンジンが応答するか確認する
+    // 生成を始める前に呼び出し、起動していない場合は早めに失敗させる
+    pub async fn health_check(&self) -> Result<String, String> {
+        self.version().await.map_err(|e| {
+            format!(
+                "voicevox engine is not available at {}: {}",
+                self.base_path, e
+            )
+        })
+    }
+
+    // 音声合成用のクエリを作成
+    pub async fn create_audio_query(&self, text: &str, speaker: i32) -> Result<AudioQuery, String> {
+        let speaker = speaker.to_string();
+        let body = self
+            .send(|| {
+                self.client
+                    .post(format!("{}/audio_query", self.base_path))
+                    .query(&[("text", text), ("speaker", speaker.as_str())])
+            })
+            .await?;
+        serde_json::from_slice::<AudioQuery>(&body).map_err(|e| e.to_string())
+    }
+
+    // 音声合成(WAV)
+    pub async fn synthesis(
+        &self,
+        audio_query: &AudioQuery,
+        speaker: i32,
+    ) -> Result<Vec<u8>, String> {
+        self.send(|| {
+            self.client
+                .post(format!("{}/synthesis", self.base_path))
+                .query(&[("speaker", speaker.to_string())])
+                .json(audio_query)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(0), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        // 上限を超えない
+        assert_eq!(policy.delay(10), Duration::from_secs(10));
+        assert_eq!(policy.delay(40), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_send_retry() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // 503、200、503の順に返すサーバー
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_path = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            for response in [
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 8\r\nConnection: close\r\n\r\n\"0.14.0\"",
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            ] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        let policy = RetryPolicy {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let client = Client::with_policy(base_path.clone(), policy).unwrap();
+        assert_eq!(client.version().await.unwrap(), "0.14.0");
+
+        // 再試行しない場合はサーバーエラーを返す
+        let client = Client::with_policy(
+            base_path,
+            RetryPolicy {
+                retries: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let e = client.version().await.unwrap_err();
+        assert!(e.contains("503"), "{}", e);
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_mora_timings() {
+        let query: AudioQuery = serde_json::from_str(
+            r#"{
+                "accent_phrases": [
+                    {
+                        "moras": [
+                            {"text": "テ", "consonant": "t", "consonant_length": 0.25, "vowel": "e", "vowel_length": 0.25, "pitch": 5.0},
+                            {"text": "ス", "consonant": "s", "consonant_length": 0.25, "vowel": "U", "vowel_length": 0.25, "pitch": 0.0}
+                        ],
+                        "accent": 1,
+                        "pause_mora": {"text": "、", "vowel": "pau", "vowel_length": 0.5, "pitch": 0.0},
+                        "is_interrogative": false
+                    },
+                    {
+                        "moras": [
+                            {"text": "ト", "consonant": "t", "consonant_length": 0.25, "vowel": "o", "vowel_length": 0.75, "pitch": 5.0}
+                        ],
+                        "accent": 1,
+                        "pause_mora": null,
+                        "is_interrogative": false
+                    }
+                ],
+                "speedScale": 2.0,
+                "pitchScale": 0.0,
+                "intonationScale": 1.0,
+                "volumeScale": 1.0,
+                "prePhonemeLength": 0.5,
+                "postPhonemeLength": 0.1,
+                "pauseLength": null,
+                "outputSamplingRate": 24000,
+                "outputStereo": false,
+                "kana": "テ'ス、ト'"
+            }"#,
+        )
+        .unwrap();
+
+        let timings = query.mora_timings();
+        let spans = timings
+            .iter()
+            .map(|timing| (timing.text.as_str(), timing.start, timing.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            vec![("テ", 0.25, 0.5), ("ス", 0.5, 0.75), ("ト", 1.0, 1.5)]
+        );
+
+        let mut adjusted = query.clone();
+        adjusted.apply(&Prosody {
+            speed: Some(1.0),
+            pause: Some(0.5),
+            ..Default::default()
+        });
+        assert_eq!(adjusted.speed_scale, 1.0);
+        assert_eq!(
+            adjusted.post_phoneme_length,
+            query.post_phoneme_length + 0.5
+        );
+        assert_eq!(adjusted.pitch_scale, query.pitch_scale);
+
+        // 未知の項目はそのまま保持される
+        let json = serde_json::to_value(&query).unwrap();
+        assert!(json.get("pauseLength").is_some());
+    }
+}